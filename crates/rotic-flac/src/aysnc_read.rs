@@ -1,31 +1,405 @@
+//! Async counterparts of the readers, the editor and the decoder, built on tokio
+
+use std::io::{Cursor, SeekFrom};
+use std::path::Path;
+
+use crate::decoder::{checked_block, first_stream_info, metadata_len, FrameBuffer, Next};
 use crate::error::Error::*;
-use crate::metadata::{Block, BlockBytes};
-use crate::{const_array, Result};
-use tokio::io::{AsyncRead, AsyncReadExt};
+use crate::frame::{AudioBlock, DecodedFrame};
+use crate::id3::Id3v2Tag;
+use crate::metadata::{BlockBytes, BlockHeader, BlockType, SeekTable, StreamInfo};
+use crate::ogg::{AsyncOggFlacReader, AsyncOggSource, Source, OGG_MAGIC};
+use crate::read::MetadataState;
+use crate::seek::{SampleSeek, SeekStep};
+use crate::verify::Verifier;
+use crate::{Result, Verification};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, BufReader};
+
+/// Async counterpart of [`MetadataReader`](crate::MetadataReader)
+pub struct AsyncMetadataReader<R> {
+    reader: R,
+    state: MetadataState,
+}
+
+impl<R: AsyncRead + Unpin> AsyncMetadataReader<R> {
+    /// Checks the `fLaC` marker, leaving `reader` at the first block header.
+    ///
+    /// An ID3v2 tag in front of the marker is skipped and kept, see [`id3v2`](Self::id3v2).
+    pub async fn new(mut reader: R) -> Result<AsyncMetadataReader<R>> {
+        let mut state = MetadataState::new();
+        while let Some((buf, len)) = state.handshake()? {
+            (&mut reader).take(len).read_to_end(buf).await?;
+        }
+        Ok(Self { reader, state })
+    }
+    /// Header of the next block, skipping the unread payload of the current one
+    pub async fn next_header(&mut self) -> Result<Option<BlockHeader>> {
+        let remaining = self.state.take_remaining();
+        let mut skipped = (&mut self.reader).take(remaining);
+        let skipped = tokio::io::copy(&mut skipped, &mut tokio::io::sink()).await?;
+        self.state.consumed(remaining, skipped)?;
+        if self.state.done() {
+            return Ok(None);
+        }
+        let mut four = [0; 4];
+        self.reader.read_exact(&mut four).await?;
+        Ok(Some(self.state.header(four)))
+    }
+    /// Payload of the current block
+    pub async fn read_data(&mut self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let remaining = self.state.take_remaining();
+        (&mut self.reader)
+            .take(remaining)
+            .read_to_end(&mut buf)
+            .await?;
+        self.state.consumed(remaining, buf.len() as u64)?;
+        Ok(buf)
+    }
+    pub async fn read_block<B: BlockType>(&mut self) -> Result<B> {
+        let offset = self.state.position();
+        let data = self.read_data().await?;
+        B::from_bytes(data).map_err(|e| e.in_block(B::BLOCK_TYPE, Some(offset)))
+    }
+    /// Offset of the next unread byte from the `fLaC` marker
+    pub fn position(&self) -> u64 {
+        self.state.position()
+    }
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> AsyncMetadataReader<R> {
+    /// ID3v2 tag found in front of the `fLaC` marker
+    pub fn id3v2(&self) -> Option<&Id3v2Tag> {
+        self.state.id3v2()
+    }
+    pub fn take_id3v2(&mut self) -> Option<Id3v2Tag> {
+        self.state.take_id3v2()
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncMetadataReader<R> {
+    /// Moves past the payload of the current block without reading it
+    pub async fn skip_data(&mut self) -> Result<()> {
+        if self.state.needs_end() {
+            let start = self.reader.stream_position().await?;
+            let end = self.reader.seek(SeekFrom::End(0)).await?;
+            self.reader.seek(SeekFrom::Start(start)).await?;
+            self.state.set_end(end.saturating_sub(start));
+        }
+        let (remaining, skipped) = self.state.skip();
+        self.reader.seek(SeekFrom::Current(skipped as i64)).await?;
+        self.state.consumed(remaining, skipped)
+    }
+}
 
+/// Async counterpart of [`read_from_stream`](crate::read_from_stream), for native and Ogg FLAC
 pub async fn read_from_async_stream<R: AsyncRead + Unpin>(
     stream: &mut R,
 ) -> Result<Vec<BlockBytes>> {
+    let metadata = match read_with_id3v2_async(&mut *stream).await {
+        Err(BadMagic(magic)) if &magic == OGG_MAGIC => {
+            let mut ogg = AsyncOggFlacReader::new((&magic[..]).chain(stream));
+            read_with_id3v2_async(&mut ogg).await
+        }
+        metadata => metadata,
+    };
+    metadata.map(|(_, blocks)| blocks)
+}
+
+/// Async counterpart of [`read_with_id3v2`](crate::read_with_id3v2)
+pub async fn read_with_id3v2_async<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<(Option<Id3v2Tag>, Vec<BlockBytes>)> {
+    let mut reader = AsyncMetadataReader::new(stream).await?;
     let mut blocks = Vec::new();
-    let mut four = [0; 4];
-    stream.read_exact(&mut four).await?;
-    if &four != b"fLaC" {
-        return Err(InvalidFormat);
-    }
-
-    loop {
-        stream.read_exact(&mut four).await?;
-        let end = (four[0] & 0x80) == 128;
-        let ty = four[0] & 0x7F;
-        let size = u32::from_be_bytes(const_array!(@start(0), four => 1, 2, 3));
-        let mut block_buf = Vec::new();
-        stream.take(size as u64).read_to_end(&mut block_buf).await?;
-        if block_buf.len() != size as usize {
-            return Err(InvalidFormat);
-        }
-        blocks.push(Block::new(end, ty, size, block_buf));
-        if end {
-            break Ok(blocks);
+    while let Some(header) = reader.next_header().await? {
+        blocks.push(header.into_block(reader.read_data().await?));
+    }
+    Ok((reader.take_id3v2(), blocks))
+}
+
+pub async fn read_from_async_path(path: impl AsRef<Path>) -> Result<Vec<BlockBytes>> {
+    read_from_async_stream(&mut BufReader::new(File::open(path).await?)).await
+}
+
+/// Async counterpart of [`find_meta`](crate::find_meta), for native and Ogg FLAC
+pub async fn find_meta_async<B: BlockType, R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<Option<B>> {
+    match AsyncMetadataReader::new(&mut *stream).await {
+        Err(BadMagic(magic)) if &magic == OGG_MAGIC => {
+            let ogg = AsyncOggFlacReader::new((&magic[..]).chain(stream));
+            find_block(AsyncMetadataReader::new(ogg).await?).await
+        }
+        reader => find_block(reader?).await,
+    }
+}
+
+async fn find_block<B: BlockType, R: AsyncRead + Unpin>(
+    mut reader: AsyncMetadataReader<R>,
+) -> Result<Option<B>> {
+    while let Some(header) = reader.next_header().await? {
+        if header.is::<B>() {
+            return reader.read_block().await.map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Async counterpart of [`Decoder::verify`](crate::Decoder::verify), reading the whole stream
+pub async fn verify_async_stream<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Verification> {
+    AsyncDecoder::new(stream).await?.verify().await
+}
+
+/// Async counterpart of [`Decoder`](crate::Decoder)
+pub struct AsyncDecoder<R> {
+    reader: Source<R, AsyncOggSource<R>>,
+    id3v2: Option<Id3v2Tag>,
+    blocks: Vec<BlockBytes>,
+    stream_info: StreamInfo,
+    /// Stream offset of the first frame
+    audio_offset: u64,
+    frames: FrameBuffer,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecoder<R> {
+    /// Reads the metadata blocks, leaving `reader` at the first frame
+    pub async fn new(mut reader: R) -> Result<AsyncDecoder<R>> {
+        let (reader, (id3v2, blocks)) = match read_with_id3v2_async(&mut reader).await {
+            Err(BadMagic(magic)) if &magic == OGG_MAGIC => {
+                let mut ogg = AsyncOggFlacReader::new(Cursor::new(magic).chain(reader));
+                let metadata = read_with_id3v2_async(&mut ogg).await?;
+                (Source::Ogg(ogg), metadata)
+            }
+            metadata => (Source::Native(reader), metadata?),
+        };
+        let stream_info = first_stream_info(&blocks)?;
+        let audio_offset = metadata_len(&blocks);
+        Ok(Self {
+            reader,
+            id3v2,
+            blocks,
+            stream_info,
+            audio_offset,
+            frames: FrameBuffer::new(audio_offset),
+        })
+    }
+    pub fn stream_info(&self) -> &StreamInfo {
+        &self.stream_info
+    }
+    pub fn blocks(&self) -> &[BlockBytes] {
+        &self.blocks
+    }
+    /// ID3v2 tag found in front of the `fLaC` marker
+    pub fn id3v2(&self) -> Option<&Id3v2Tag> {
+        self.id3v2.as_ref()
+    }
+    pub fn find_meta<B: BlockType>(&self) -> Option<Result<B>> {
+        self.blocks.iter().find(|b| b.is::<B>()).map(|b| {
+            B::from_bytes(b.block_data().clone()).map_err(|e| e.in_block(B::BLOCK_TYPE, None))
+        })
+    }
+    pub fn into_inner(self) -> R {
+        match self.reader {
+            Source::Native(reader) => reader,
+            Source::Ogg(reader) => reader.into_inner().into_inner().1,
+        }
+    }
+
+    async fn fill(&mut self) -> Result<()> {
+        let (buf, want) = self.frames.spare();
+        let read = (&mut self.reader).take(want).read_to_end(buf).await?;
+        self.frames.set_eof(read == 0);
+        Ok(())
+    }
+
+    async fn next_frame(&mut self) -> Result<Option<(u64, DecodedFrame)>> {
+        loop {
+            match self.frames.next(&self.stream_info)? {
+                Next::Frame(offset, frame) => return Ok(Some((offset, frame))),
+                Next::End => return Ok(None),
+                Next::Fill => self.fill().await?,
+            }
+        }
+    }
+
+    /// Decodes the next frame, `None` at the end of the stream
+    pub async fn read_frame(&mut self) -> Result<Option<AudioBlock>> {
+        checked_block(self.next_frame().await?)
+    }
+
+    /// Decodes the remaining frames, checking their CRC-16 and the audio MD5 in STREAMINFO
+    pub async fn verify(mut self) -> Result<Verification> {
+        let mut verifier = Verifier::new(&self.stream_info);
+        loop {
+            match verifier.next(&mut self.frames, &self.stream_info)? {
+                Next::Frame(..) => {}
+                Next::Fill => self.fill().await?,
+                Next::End => return Ok(verifier.finish()),
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncDecoder<R> {
+    /// Async counterpart of [`Decoder::seek_to_sample`](crate::Decoder::seek_to_sample)
+    pub async fn seek_to_sample(&mut self, sample: u64) -> Result<Option<AudioBlock>> {
+        // position of `fLaC` in the reader
+        let base = self.reader.stream_position().await? - self.frames.end();
+        let end = self.reader.seek(SeekFrom::End(0)).await? - base;
+
+        let seek_table = self.find_meta::<SeekTable>().and_then(Result::ok);
+        let mut seek = SampleSeek::new(sample, self.audio_offset, end, seek_table.as_ref());
+        loop {
+            match seek.next(&mut self.frames, &self.stream_info)? {
+                SeekStep::Seek(offset) => {
+                    self.reader.seek(SeekFrom::Start(base + offset)).await?;
+                }
+                SeekStep::Fill => self.fill().await?,
+                SeekStep::Done(block) => return Ok(block),
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::metadata::VorbisComment;
+    use crate::{encode, Decoder, EncoderConfig, MetadataEditor};
+
+    fn stream() -> Vec<u8> {
+        let samples: Vec<i32> = (0..300_000i64)
+            .map(|i| (i * 7919 % 4001 - 2000) as i32)
+            .collect();
+        let mut config = EncoderConfig::new(8000, 2, 16).level(0);
+        config.seek_point_interval = 0;
+        encode(&samples, config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn read_and_find() {
+        let bytes = stream();
+        let mut reader = AsyncMetadataReader::new(Cursor::new(&bytes)).await.unwrap();
+        let mut types = Vec::new();
+        while let Some(header) = reader.next_header().await.unwrap() {
+            types.push(header.block_type);
+            reader.skip_data().await.unwrap();
+        }
+        assert_eq!(types, [0, 4, 1]);
+        let cut = &bytes[..50];
+        let mut reader = AsyncMetadataReader::new(Cursor::new(cut)).await.unwrap();
+        reader.next_header().await.unwrap();
+        reader.skip_data().await.unwrap();
+        reader.next_header().await.unwrap();
+        assert!(matches!(
+            reader.skip_data().await,
+            Err(Truncated { offset: 46, .. })
+        ));
+        let blocks = read_from_async_stream(&mut &bytes[..]).await.unwrap();
+        assert_eq!(blocks.len(), 3);
+        let comment: Option<VorbisComment> = find_meta_async(&mut &bytes[..]).await.unwrap();
+        assert!(comment.unwrap().vendor().starts_with("rotic-flac"));
+
+        let tag = crate::id3::tests::tag(false);
+        let prefixed = [&tag[..], &bytes].concat();
+        let (id3v2, blocks) = read_with_id3v2_async(&mut &prefixed[..]).await.unwrap();
+        assert_eq!(id3v2.unwrap().as_bytes(), tag);
+        assert_eq!(blocks.len(), 3);
+        let decoder = AsyncDecoder::new(&prefixed[..]).await.unwrap();
+        assert_eq!(decoder.id3v2().unwrap().len(), tag.len());
+        let editor = MetadataEditor::read_from_async(&mut &prefixed[..])
+            .await
+            .unwrap();
+        let mut out = Vec::new();
+        editor
+            .write_to_async(&mut Cursor::new(&prefixed), &mut out)
+            .await
+            .unwrap();
+        assert_eq!(out, prefixed);
+    }
+
+    #[tokio::test]
+    async fn decode_and_seek() {
+        let bytes = stream();
+        let sync: Vec<_> = Decoder::new(&bytes[..])
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let mut decoder = AsyncDecoder::new(Cursor::new(&bytes)).await.unwrap();
+        for block in &sync {
+            assert_eq!(decoder.read_frame().await.unwrap().as_ref(), Some(block));
+        }
+        assert!(decoder.read_frame().await.unwrap().is_none());
+
+        let mut sync_decoder = Decoder::new(Cursor::new(&bytes)).unwrap();
+        for target in [123_456, 7, 149_999] {
+            let block = decoder.seek_to_sample(target).await.unwrap().unwrap();
+            assert_eq!(block.first_sample(), target);
+            assert_eq!(Some(block), sync_decoder.seek_to_sample(target).unwrap());
+        }
+
+        let verification = verify_async_stream(&mut &bytes[..]).await.unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(
+            verification,
+            Decoder::new(&bytes[..]).unwrap().verify().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn edit_and_save() {
+        let path = std::env::temp_dir().join(format!("rotic-async-{}.flac", std::process::id()));
+        let bytes = stream();
+        tokio::fs::write(&path, &bytes).await.unwrap();
+
+        let mut editor = MetadataEditor::open_async(&path).await.unwrap();
+        let mut comment: VorbisComment = editor.find().unwrap().unwrap();
+        comment.add("TITLE", "async");
+        editor.set(comment);
+        editor.save_to_path_async(&path).await.unwrap();
+        assert_eq!(tokio::fs::read(&path).await.unwrap().len(), bytes.len());
+
+        let mut editor = MetadataEditor::open_async(&path).await.unwrap();
+        editor.set_padding(0);
+        let mut comment: VorbisComment = editor.find().unwrap().unwrap();
+        comment.add("LYRICS", "x".repeat(10_000));
+        editor.set(comment);
+        #[cfg(unix)]
+        use std::os::unix::fs::PermissionsExt;
+        #[cfg(unix)]
+        tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640))
+            .await
+            .unwrap();
+        editor.save_to_path_async(&path).await.unwrap();
+        #[cfg(unix)]
+        assert_eq!(
+            tokio::fs::metadata(&path)
+                .await
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777,
+            0o640
+        );
+        let saved = tokio::fs::read(&path).await.unwrap();
+        let comment: VorbisComment = crate::find_meta_from_bytes(&saved).unwrap().unwrap();
+        assert_eq!(comment.title(), Some("async"));
+        let audio_offset = metadata_len(&read_from_async_path(&path).await.unwrap());
+        let original_offset = metadata_len(&crate::read_from_bytes(&bytes).unwrap());
+        assert_eq!(
+            saved[audio_offset as usize..],
+            bytes[original_offset as usize..]
+        );
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
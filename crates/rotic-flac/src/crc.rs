@@ -0,0 +1,70 @@
+const fn crc8_table() -> [u8; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC8_TABLE: [u8; 256] = crc8_table();
+static CRC16_TABLE: [u16; 256] = crc16_table();
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// CRC-8 with polynomial x^8 + x^2 + x^1 + x^0, used by frame headers
+pub(crate) fn crc8_update(crc: u8, byte: u8) -> u8 {
+    CRC8_TABLE[(crc ^ byte) as usize]
+}
+
+/// CRC-16 with polynomial x^16 + x^15 + x^2 + x^0, used by frame footers
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, b| {
+        (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ b) as usize]
+    })
+}
+
+/// CRC-32 with polynomial 0x04C11DB7, unreflected and without final XOR, used by Ogg pages
+pub(crate) fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, b| {
+        (crc << 8) ^ CRC32_TABLE[((crc >> 24) as u8 ^ b) as usize]
+    })
+}
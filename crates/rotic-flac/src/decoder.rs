@@ -0,0 +1,468 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::error::Error::*;
+use crate::frame::{decode_frame, is_eof, AudioBlock, DecodedFrame};
+use crate::metadata::{BlockBytes, BlockType, SeekTable, StreamInfo};
+use crate::ogg::{OggFlacReader, OggSource, Source, OGG_MAGIC};
+use crate::seek::{SampleSeek, SeekStep};
+use crate::verify::{Verification, Verifier};
+use crate::{read_with_id3v2, Id3v2Tag, Result};
+
+/// Bytes read from the source at a time
+const READ_SIZE: usize = 64 * 1024;
+/// Seeking decodes frames in order once the target is within this many bytes
+pub(crate) const LINEAR_SEEK_LEN: u64 = 2 * READ_SIZE as u64;
+
+/// Buffered audio data, shared by the sync and async frame readers
+pub(crate) struct FrameBuffer {
+    buf: Vec<u8>,
+    pos: usize,
+    /// Stream offset of `buf[0]`
+    offset: u64,
+    eof: bool,
+}
+
+pub(crate) enum Next {
+    /// A frame and its offset in the stream
+    Frame(u64, DecodedFrame),
+    /// More data has to be read with [`FrameBuffer::spare`]
+    Fill,
+    End,
+}
+
+impl FrameBuffer {
+    pub(crate) fn new(offset: u64) -> FrameBuffer {
+        Self {
+            buf: Vec::new(),
+            pos: 0,
+            offset,
+            eof: false,
+        }
+    }
+    /// Drops consumed data, returns the buffer and how many bytes to append to it
+    pub(crate) fn spare(&mut self) -> (&mut Vec<u8>, u64) {
+        self.buf.drain(..self.pos);
+        self.offset += self.pos as u64;
+        self.pos = 0;
+        let want = READ_SIZE.max(self.buf.len());
+        (&mut self.buf, want as u64)
+    }
+    pub(crate) fn set_eof(&mut self, eof: bool) {
+        self.eof = eof;
+    }
+    /// Discards the buffer, the next data read starts at `offset`
+    pub(crate) fn reset(&mut self, offset: u64) {
+        *self = Self::new(offset);
+    }
+    /// Stream offset of the next unread byte
+    pub(crate) fn position(&self) -> u64 {
+        self.offset + self.pos as u64
+    }
+    /// Stream offset of the end of the buffered data
+    pub(crate) fn end(&self) -> u64 {
+        self.offset + self.buf.len() as u64
+    }
+    pub(crate) fn next(&mut self, stream_info: &StreamInfo) -> Result<Next> {
+        let rest = &self.buf[self.pos..];
+        if rest.len() < 2 {
+            return Ok(if self.eof { Next::End } else { Next::Fill });
+        }
+        match decode_frame(rest, stream_info) {
+            Ok(frame) => {
+                let offset = self.offset + self.pos as u64;
+                self.pos += frame.len;
+                Ok(Next::Frame(offset, frame))
+            }
+            Err(e) if is_eof(&e) && !self.eof => Ok(Next::Fill),
+            // trailing data which isn't a frame, such as an ID3v1 tag
+            Err(_) if self.eof && !(rest[0] == 0xFF && rest[1] & 0xFE == 0xF8) => Ok(Next::End),
+            Err(e) => Err(e.offset_by(self.position())),
+        }
+    }
+    /// Like [`next`](Self::next), but skips anything until a frame with a valid CRC
+    pub(crate) fn next_valid(&mut self, stream_info: &StreamInfo) -> Result<Next> {
+        self.resync(stream_info, true)
+    }
+    /// Skips anything until a frame that decodes, with a valid CRC-16 if `crc` is set
+    pub(crate) fn resync(&mut self, stream_info: &StreamInfo, crc: bool) -> Result<Next> {
+        loop {
+            let rest = &self.buf[self.pos..];
+            let Some(sync) = rest
+                .windows(2)
+                .position(|w| w[0] == 0xFF && w[1] & 0xFE == 0xF8)
+            else {
+                // the last byte could start a sync code
+                self.pos = self.buf.len().saturating_sub(1).max(self.pos);
+                return Ok(if self.eof { Next::End } else { Next::Fill });
+            };
+            self.pos += sync;
+            match decode_frame(&self.buf[self.pos..], stream_info) {
+                Ok(frame) if frame.crc_valid || !crc => {
+                    let offset = self.position();
+                    self.pos += frame.len;
+                    return Ok(Next::Frame(offset, frame));
+                }
+                Err(e) if is_eof(&e) && !self.eof => return Ok(Next::Fill),
+                _ => self.pos += 1,
+            }
+        }
+    }
+}
+
+/// STREAMINFO, which has to be the first metadata block
+pub(crate) fn first_stream_info(blocks: &[BlockBytes]) -> Result<StreamInfo> {
+    match blocks.first() {
+        Some(block) if block.is::<StreamInfo>() => {
+            Ok(block.clone().convert::<StreamInfo>()?.into_inner())
+        }
+        _ => Err(InvalidFormat),
+    }
+}
+
+/// The audio of a decoded frame, failing if its CRC-16 doesn't match
+pub(crate) fn checked_block(frame: Option<(u64, DecodedFrame)>) -> Result<Option<AudioBlock>> {
+    match frame {
+        Some((offset, frame)) if !frame.crc_valid => Err(CrcMismatch {
+            frame: frame.block.header().number(),
+            offset,
+        }),
+        frame => Ok(frame.map(|(_, frame)| frame.block)),
+    }
+}
+
+/// Length of the `fLaC` marker and the metadata blocks
+pub(crate) fn metadata_len(blocks: &[BlockBytes]) -> u64 {
+    blocks
+        .iter()
+        .fold(4, |len, b| len + 4 + b.block_data().len() as u64)
+}
+
+/// Decodes the audio frames of a FLAC stream into interleaved samples. Ogg FLAC is read as
+/// well, but can't be seeked in
+pub struct Decoder<R> {
+    reader: Source<R, OggSource<R>>,
+    id3v2: Option<Id3v2Tag>,
+    blocks: Vec<BlockBytes>,
+    stream_info: StreamInfo,
+    /// Stream offset of the first frame
+    audio_offset: u64,
+    frames: FrameBuffer,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Reads the metadata blocks, leaving `reader` at the first frame
+    pub fn new(mut reader: R) -> Result<Decoder<R>> {
+        let (reader, (id3v2, blocks)) = match read_with_id3v2(&mut reader) {
+            Err(BadMagic(magic)) if &magic == OGG_MAGIC => {
+                let mut ogg = OggFlacReader::new(io::Cursor::new(magic).chain(reader));
+                let metadata = read_with_id3v2(&mut ogg)?;
+                (Source::Ogg(ogg), metadata)
+            }
+            metadata => (Source::Native(reader), metadata?),
+        };
+        let stream_info = first_stream_info(&blocks)?;
+        let audio_offset = metadata_len(&blocks);
+        Ok(Self {
+            reader,
+            id3v2,
+            blocks,
+            stream_info,
+            audio_offset,
+            frames: FrameBuffer::new(audio_offset),
+        })
+    }
+    pub fn stream_info(&self) -> &StreamInfo {
+        &self.stream_info
+    }
+    pub fn blocks(&self) -> &[BlockBytes] {
+        &self.blocks
+    }
+    /// ID3v2 tag found in front of the `fLaC` marker
+    pub fn id3v2(&self) -> Option<&Id3v2Tag> {
+        self.id3v2.as_ref()
+    }
+    pub fn find_meta<B: BlockType>(&self) -> Option<Result<B>> {
+        self.blocks.iter().find(|b| b.is::<B>()).map(|b| {
+            B::from_bytes(b.block_data().clone()).map_err(|e| e.in_block(B::BLOCK_TYPE, None))
+        })
+    }
+    pub fn into_inner(self) -> R {
+        match self.reader {
+            Source::Native(reader) => reader,
+            Source::Ogg(reader) => reader.into_inner().into_inner().1,
+        }
+    }
+
+    fn fill(&mut self) -> Result<()> {
+        let (buf, want) = self.frames.spare();
+        let read = (&mut self.reader).take(want).read_to_end(buf)?;
+        self.frames.set_eof(read == 0);
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<(u64, DecodedFrame)>> {
+        loop {
+            match self.frames.next(&self.stream_info)? {
+                Next::Frame(offset, frame) => return Ok(Some((offset, frame))),
+                Next::End => return Ok(None),
+                Next::Fill => self.fill()?,
+            }
+        }
+    }
+
+    /// Decodes the next frame, `None` at the end of the stream
+    pub fn read_frame(&mut self) -> Result<Option<AudioBlock>> {
+        checked_block(self.next_frame()?)
+    }
+
+    /// Decodes the remaining frames, checking their CRC-16 and the audio MD5 in STREAMINFO.
+    /// Damaged frames are reported in the result rather than as an error
+    pub fn verify(mut self) -> Result<Verification> {
+        let mut verifier = Verifier::new(&self.stream_info);
+        loop {
+            match verifier.next(&mut self.frames, &self.stream_info)? {
+                Next::Frame(..) => {}
+                Next::Fill => self.fill()?,
+                Next::End => return Ok(verifier.finish()),
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Decoder<R> {
+    /// Moves to `sample` and returns the audio from there to the end of its frame,
+    /// later reads continue with the next frame. `None` if `sample` is past the end
+    pub fn seek_to_sample(&mut self, sample: u64) -> Result<Option<AudioBlock>> {
+        // position of `fLaC` in the reader
+        let base = self.reader.stream_position()? - self.frames.end();
+        let end = self.reader.seek(SeekFrom::End(0))? - base;
+
+        let seek_table = self.find_meta::<SeekTable>().and_then(Result::ok);
+        let mut seek = SampleSeek::new(sample, self.audio_offset, end, seek_table.as_ref());
+        loop {
+            match seek.next(&mut self.frames, &self.stream_info)? {
+                SeekStep::Seek(offset) => {
+                    self.reader.seek(SeekFrom::Start(base + offset))?;
+                }
+                SeekStep::Fill => self.fill()?,
+                SeekStep::Done(block) => return Ok(block),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Decoder<R> {
+    type Item = Result<AudioBlock>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::crc::{crc16, crc8_update};
+    use crate::metadata::Block;
+    use crate::{encode, EncoderConfig, MetadataEditor};
+
+    #[derive(Default)]
+    struct Bits {
+        bytes: Vec<u8>,
+        bit: u32,
+    }
+
+    impl Bits {
+        fn put(&mut self, value: i64, bits: u32) {
+            for i in (0..bits).rev() {
+                if self.bit == 0 {
+                    self.bytes.push(0);
+                }
+                let bit = ((value >> i) & 1) as u8;
+                *self.bytes.last_mut().unwrap() |= bit << (7 - self.bit);
+                self.bit = (self.bit + 1) % 8;
+            }
+        }
+        fn rice(&mut self, value: i64, k: u32) {
+            let folded = ((value << 1) ^ (value >> 63)) as u64;
+            for _ in 0..folded >> k {
+                self.put(0, 1);
+            }
+            self.put(1, 1);
+            self.put(folded as i64, k);
+        }
+    }
+
+    #[test]
+    fn decode_left_side_frame() {
+        let left: Vec<i64> = (0..16).map(|i| i * i - 40).collect();
+        let right: Vec<i64> = left.iter().map(|l| l + 3).collect();
+
+        let stream_info = StreamInfo {
+            min_block_size: 16,
+            max_block_size: 16,
+            sample_rate: 44100,
+            channels: 2,
+            bps: 16,
+            total_samples: 16,
+            ..Default::default()
+        };
+        let mut bytes = b"fLaC".to_vec();
+        bytes.extend(Block::new(true, 0, 34, stream_info).to_bytes());
+
+        let mut frame = Bits::default();
+        for (value, bits) in [(0xFFF8, 16), (0b0110, 4), (0b1001, 4), (0b1000, 4), (0b100, 3)] {
+            frame.put(value, bits);
+        }
+        frame.put(0, 1);
+        frame.put(0, 8);
+        frame.put(15, 8);
+        let crc = frame.bytes.iter().fold(0, |crc, b| crc8_update(crc, *b));
+        frame.put(crc as i64, 8);
+        // left: FIXED order 2, one Rice partition with parameter 2
+        frame.put(0b0001_0100, 8);
+        frame.put(left[0], 16);
+        frame.put(left[1], 16);
+        frame.put(0, 2);
+        frame.put(0, 4);
+        frame.put(2, 4);
+        for i in 2..16 {
+            frame.rice(left[i] - (2 * left[i - 1] - left[i - 2]), 2);
+        }
+        // side: CONSTANT
+        frame.put(0, 8);
+        frame.put(-3, 17);
+        frame.bit = 0;
+        let crc = crc16(&frame.bytes);
+        frame.put(crc as i64, 16);
+        bytes.extend(frame.bytes);
+
+        let mut decoder = Decoder::new(&bytes[..]).unwrap();
+        assert_eq!(decoder.stream_info().sample_rate, 44100);
+        let block = decoder.next().unwrap().unwrap();
+        assert_eq!(block.block_size(), 16);
+        assert_eq!(block.channel(0).map(|s| s as i64).collect::<Vec<_>>(), left);
+        assert_eq!(block.channel(1).map(|s| s as i64).collect::<Vec<_>>(), right);
+        assert!(decoder.next().is_none());
+
+        *bytes.last_mut().unwrap() ^= 1;
+        assert!(Decoder::new(&bytes[..]).unwrap().next().unwrap().is_err());
+    }
+
+    #[test]
+    fn reject_overflowing_prediction() {
+        let stream_info = StreamInfo {
+            min_block_size: 256,
+            max_block_size: 4096,
+            sample_rate: 44100,
+            channels: 1,
+            bps: 16,
+            ..Default::default()
+        };
+        // 4096 samples overflow i64, 256 only leave the 16 bit range
+        for (code, block_size) in [(0b1100, 4096), (0b1000, 256)] {
+            let mut bytes = b"fLaC".to_vec();
+            bytes.extend(Block::new(true, 0, 34, stream_info.clone()).to_bytes());
+
+            let mut frame = Bits::default();
+            for (value, bits) in [
+                (0xFFF8, 16),
+                (code, 4),
+                (0b1001, 4),
+                (0b0000, 4),
+                (0b100, 3),
+            ] {
+                frame.put(value, bits);
+            }
+            frame.put(0, 1);
+            frame.put(0, 8);
+            let crc = frame.bytes.iter().fold(0, |crc, b| crc8_update(crc, *b));
+            frame.put(crc as i64, 8);
+            // FIXED order 4, one escaped partition of maximal 31 bit residuals
+            frame.put(0b0001_1000, 8);
+            for _ in 0..4 {
+                frame.put(i16::MAX as i64, 16);
+            }
+            frame.put(0, 2);
+            frame.put(0, 4);
+            frame.put(15, 4);
+            frame.put(31, 5);
+            for _ in 4..block_size {
+                frame.put(0x3FFF_FFFF, 31);
+            }
+            frame.bit = 0;
+            let crc = crc16(&frame.bytes);
+            frame.put(crc as i64, 16);
+            bytes.extend(frame.bytes);
+
+            let mut decoder = Decoder::new(&bytes[..]).unwrap();
+            assert!(matches!(decoder.next(), Some(Err(InvalidFormat))));
+        }
+    }
+
+    #[test]
+    fn seek_to_sample() {
+        let samples: Vec<i32> = (0..200_000)
+            .map(|i: i32| (i.wrapping_mul(7919) % 4001 - 2000) + (i / 2 % 300))
+            .collect();
+        let total = samples.len() as u64 / 2;
+        for seek_point_interval in [1, 0] {
+            let mut config = EncoderConfig::new(8000, 2, 16).level(0);
+            config.seek_point_interval = seek_point_interval;
+            let mut bytes = vec![0xAA; 5];
+            bytes.extend(encode(&samples, config).unwrap());
+            let mut reader = Cursor::new(bytes);
+            reader.set_position(5);
+
+            let mut decoder = Decoder::new(reader).unwrap();
+            for target in [70_000, 0, 1, 1151, 1152, 12_345, 54_321, total - 1, 99_000] {
+                let block = decoder.seek_to_sample(target).unwrap().unwrap();
+                assert_eq!(block.first_sample(), target);
+                let mut decoded = block.into_samples();
+                if let Some(next) = decoder.next() {
+                    decoded.extend(next.unwrap().samples());
+                }
+                let start = target as usize * 2;
+                let end = (start + decoded.len()).min(samples.len());
+                assert_eq!(decoded[..end - start], samples[start..end]);
+            }
+            assert!(decoder.seek_to_sample(total).unwrap().is_none());
+            assert!(decoder.next().is_none());
+        }
+    }
+
+    #[test]
+    fn seek_with_malformed_seek_table() {
+        let samples: Vec<i32> = (0..100_000).map(|i| i % 3001 - 1500).collect();
+        let mut config = EncoderConfig::new(8000, 1, 16).level(0);
+        config.seek_point_interval = 1;
+        let bytes = encode(&samples, config).unwrap();
+
+        let mut editor = MetadataEditor::read_from(&mut &bytes[..]).unwrap();
+        let mut seek_table: SeekTable = editor.find().unwrap().unwrap();
+        assert!(seek_table.len() > 4);
+        let targets: Vec<_> = seek_table
+            .seek_points()
+            .map(|p| p.sample_number + 10)
+            .collect();
+        // every other point in front of the one before it, and an offset past the end
+        for point in seek_table.points_mut().iter_mut().skip(1).step_by(2) {
+            point.stream_offset = 0;
+        }
+        seek_table.points_mut()[2].stream_offset = u64::MAX - 10;
+        editor.set(seek_table);
+        let mut malformed = Vec::new();
+        editor
+            .write_to(&mut Cursor::new(&bytes), &mut malformed)
+            .unwrap();
+
+        let mut decoder = Decoder::new(Cursor::new(malformed)).unwrap();
+        for target in targets.into_iter().chain([99_999, 0]) {
+            let block = decoder.seek_to_sample(target).unwrap().unwrap();
+            assert_eq!(block.first_sample(), target);
+            assert_eq!(block.samples()[0], samples[target as usize]);
+        }
+    }
+}
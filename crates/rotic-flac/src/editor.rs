@@ -0,0 +1,386 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::decoder::metadata_len;
+use crate::error::Error::*;
+use crate::id3::prefix_len;
+use crate::metadata::{Block, BlockBytes, BlockType, Padding, StreamInfo};
+use crate::ogg::OGG_MAGIC;
+use crate::{read_with_id3v2, Id3v2Tag, Result};
+#[cfg(feature = "async")]
+use {
+    crate::aysnc_read::read_with_id3v2_async,
+    tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+};
+
+/// Largest payload a metadata block header can describe
+const MAX_BLOCK_SIZE: usize = 0xFF_FFFF;
+
+/// Ogg pages split the metadata blocks, an editor can't write them back
+fn native_only<T>(result: Result<T>) -> Result<T> {
+    match result {
+        Err(BadMagic(magic)) if &magic == OGG_MAGIC => Err(Unsupported("writing Ogg FLAC")),
+        result => result,
+    }
+}
+
+/// How [`MetadataEditor::save_to_path`] writes the file
+enum Save {
+    /// Overwrite the start of the file with these bytes
+    InPlace(Vec<u8>),
+    /// Write the file to this temporary path and rename it over the original
+    Rewrite(PathBuf),
+}
+
+/// Loads the metadata blocks of a FLAC stream so they can be edited and written back.
+///
+/// PADDING blocks are not kept in order: on save every PADDING block is dropped and a single
+/// one is written after the other blocks, sized so that the audio frames don't have to move
+/// whenever possible. An ID3v2 tag in front of `fLaC` is written back unless it is removed
+/// with [`set_id3v2`](Self::set_id3v2).
+#[derive(Debug, Clone)]
+pub struct MetadataEditor {
+    id3v2: Option<Id3v2Tag>,
+    /// Length of the ID3v2 tag in the source stream
+    id3v2_len: u64,
+    blocks: Vec<BlockBytes>,
+    /// Length of the metadata region in the source stream, `fLaC` included
+    metadata_len: u64,
+    padding: u32,
+}
+
+impl MetadataEditor {
+    /// PADDING appended when the file has to be rewritten
+    pub const DEFAULT_PADDING: u32 = 8192;
+
+    /// Editor for a new stream without an existing metadata region
+    pub fn new(stream_info: StreamInfo) -> MetadataEditor {
+        Self {
+            id3v2: None,
+            id3v2_len: 0,
+            blocks: vec![Block::from_block(stream_info)],
+            metadata_len: 0,
+            padding: Self::DEFAULT_PADDING,
+        }
+    }
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<MetadataEditor> {
+        let (id3v2, blocks) = native_only(read_with_id3v2(reader))?;
+        Ok(Self {
+            id3v2_len: prefix_len(id3v2.as_ref()),
+            id3v2,
+            metadata_len: metadata_len(&blocks),
+            blocks,
+            padding: Self::DEFAULT_PADDING,
+        })
+    }
+    pub fn open(path: impl AsRef<Path>) -> Result<MetadataEditor> {
+        Self::read_from(&mut io::BufReader::new(File::open(path)?))
+    }
+    pub fn blocks(&self) -> &[BlockBytes] {
+        &self.blocks
+    }
+    pub fn blocks_mut(&mut self) -> &mut Vec<BlockBytes> {
+        &mut self.blocks
+    }
+    /// Length of the metadata region the editor was loaded from
+    pub fn metadata_len(&self) -> u64 {
+        self.metadata_len
+    }
+    /// ID3v2 tag found in front of the `fLaC` marker
+    pub fn id3v2(&self) -> Option<&Id3v2Tag> {
+        self.id3v2.as_ref()
+    }
+    /// Replaces or, with `None`, strips the ID3v2 tag written in front of `fLaC`
+    pub fn set_id3v2(&mut self, tag: Option<Id3v2Tag>) {
+        self.id3v2 = tag;
+    }
+    /// Size of the PADDING block written when the audio frames have to be moved
+    pub fn set_padding(&mut self, padding: u32) {
+        self.padding = padding;
+    }
+    pub fn find<B: BlockType>(&self) -> Option<Result<B>> {
+        self.find_all().next()
+    }
+    pub fn find_all<B: BlockType>(&self) -> impl Iterator<Item = Result<B>> + '_ {
+        self.blocks.iter().filter(|b| b.is::<B>()).map(|b| {
+            B::from_bytes(b.block_data().clone()).map_err(|e| e.in_block(B::BLOCK_TYPE, None))
+        })
+    }
+    /// Replaces the first block of the same type, or appends it if there is none
+    pub fn set<B: BlockType>(&mut self, block: B) {
+        let block = Block::from_block(block);
+        match self.blocks.iter_mut().find(|b| b.is::<B>()) {
+            Some(old) => *old = block,
+            None => self.blocks.push(block),
+        }
+    }
+    pub fn push<B: BlockType>(&mut self, block: B) {
+        self.blocks.push(Block::from_block(block));
+    }
+    /// Removes every block of the type, returning how many were removed
+    pub fn remove<B: BlockType>(&mut self) -> usize {
+        let len = self.blocks.len();
+        self.blocks.retain(|b| !b.is::<B>());
+        len - self.blocks.len()
+    }
+    pub fn retain<F: FnMut(&BlockBytes) -> bool>(&mut self, f: F) {
+        self.blocks.retain(f);
+    }
+
+    /// Length of the metadata region without any PADDING
+    fn content_len(&self) -> u64 {
+        4 + self
+            .blocks
+            .iter()
+            .filter(|b| !b.is::<Padding>())
+            .map(|b| 4 + b.block_data().len() as u64)
+            .sum::<u64>()
+    }
+    /// PADDING needed to exactly fill the original metadata region, `None` if it can't be reused
+    fn in_place_padding(&self) -> Option<Option<u32>> {
+        let content_len = self.content_len();
+        if prefix_len(self.id3v2.as_ref()) != self.id3v2_len {
+            return None;
+        }
+        if self.metadata_len == 0 || content_len > self.metadata_len {
+            return None;
+        }
+        match self.metadata_len - content_len {
+            0 => Some(None),
+            1..=3 => None,
+            free if free - 4 <= MAX_BLOCK_SIZE as u64 => Some(Some((free - 4) as u32)),
+            _ => None,
+        }
+    }
+    fn id3v2_bytes(&self) -> &[u8] {
+        self.id3v2.as_ref().map_or(&[], |tag| tag.as_bytes())
+    }
+    /// Decides how to save to `path`, given the metadata `loaded` from it again
+    fn plan_save(&self, path: &Path, loaded: &MetadataEditor) -> Result<Save> {
+        if (self.id3v2_len, self.metadata_len) != (loaded.id3v2_len, loaded.metadata_len) {
+            return Err(Custom(
+                "metadata region of the file changed since it was loaded".to_owned(),
+            ));
+        }
+        if let Some(padding) = self.in_place_padding() {
+            let mut bytes = self.id3v2_bytes().to_vec();
+            bytes.extend(self.to_bytes(padding)?);
+            return Ok(Save::InPlace(bytes));
+        }
+        let mut file_name = path.file_name().unwrap_or_default().to_owned();
+        file_name.push(".rotic-tmp");
+        Ok(Save::Rewrite(path.with_file_name(file_name)))
+    }
+    /// Whether saving can overwrite the metadata region without moving the audio frames
+    pub fn fits_in_place(&self) -> bool {
+        self.in_place_padding().is_some()
+    }
+
+    /// Serializes the metadata region with the given amount of trailing PADDING, the ID3v2 tag
+    /// is not included
+    pub fn to_bytes(&self, padding: Option<u32>) -> Result<Vec<u8>> {
+        match self.blocks.first() {
+            Some(b) if b.is::<StreamInfo>() => {}
+            _ => {
+                return Err(Custom(
+                    "STREAMINFO must be the first metadata block".to_owned(),
+                ))
+            }
+        }
+        let mut blocks: Vec<_> = self.blocks.iter().filter(|b| !b.is::<Padding>()).collect();
+        let padding = padding.map(|len| Block::from_block(Padding::new(len as usize)));
+        blocks.extend(padding.as_ref());
+
+        let mut buf = Vec::with_capacity(self.content_len() as usize);
+        buf.extend_from_slice(b"fLaC");
+        let last = blocks.len() - 1;
+        for (i, block) in blocks.into_iter().enumerate() {
+            let data = block.block_data();
+            if data.len() > MAX_BLOCK_SIZE {
+                return Err(Custom(format!(
+                    "{} block is larger than {MAX_BLOCK_SIZE} bytes",
+                    block.block_type_str()
+                )));
+            }
+            buf.push(block.block_type() | ((i == last) as u8) << 7);
+            buf.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+            buf.extend_from_slice(data);
+        }
+        Ok(buf)
+    }
+
+    /// Writes the edited metadata followed by the audio frames of `source` to `dest`
+    pub fn write_to<R: Read + Seek, W: Write>(&self, source: &mut R, dest: &mut W) -> Result<()> {
+        let padding = self.in_place_padding().unwrap_or(Some(self.padding));
+        dest.write_all(self.id3v2_bytes())?;
+        dest.write_all(&self.to_bytes(padding)?)?;
+        source.seek(SeekFrom::Start(self.id3v2_len + self.metadata_len))?;
+        io::copy(source, dest)?;
+        Ok(())
+    }
+
+    /// Saves the metadata to the file it was loaded from.
+    ///
+    /// The metadata region is overwritten in place when the edited blocks fit by growing or
+    /// shrinking PADDING, otherwise the file is rewritten through a temporary file next to it.
+    pub fn save_to_path(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let loaded = Self::read_from(&mut io::BufReader::new(&mut file))?;
+        let tmp_path = match self.plan_save(path, &loaded)? {
+            Save::InPlace(bytes) => {
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(&bytes)?;
+                return Ok(file.flush()?);
+            }
+            Save::Rewrite(tmp_path) => tmp_path,
+        };
+        let result = (|| {
+            let mut tmp = io::BufWriter::new(File::create(&tmp_path)?);
+            self.write_to(&mut file, &mut tmp)?;
+            let tmp = tmp.into_inner().map_err(|e| e.into_error())?;
+            // keep the mode of the file being replaced, not the default one
+            tmp.set_permissions(file.metadata()?.permissions())?;
+            tmp.sync_all()?;
+            drop(file);
+            std::fs::rename(&tmp_path, path)?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        result
+    }
+}
+
+#[cfg(feature = "async")]
+impl MetadataEditor {
+    pub async fn read_from_async<R: AsyncRead + Unpin>(reader: &mut R) -> Result<MetadataEditor> {
+        let (id3v2, blocks) = native_only(read_with_id3v2_async(reader).await)?;
+        Ok(Self {
+            id3v2_len: prefix_len(id3v2.as_ref()),
+            id3v2,
+            metadata_len: metadata_len(&blocks),
+            blocks,
+            padding: Self::DEFAULT_PADDING,
+        })
+    }
+    pub async fn open_async(path: impl AsRef<Path>) -> Result<MetadataEditor> {
+        let file = tokio::fs::File::open(path).await?;
+        Self::read_from_async(&mut tokio::io::BufReader::new(file)).await
+    }
+
+    /// Async counterpart of [`write_to`](Self::write_to)
+    pub async fn write_to_async<R, W>(&self, source: &mut R, dest: &mut W) -> Result<()>
+    where
+        R: AsyncRead + AsyncSeek + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let padding = self.in_place_padding().unwrap_or(Some(self.padding));
+        dest.write_all(self.id3v2_bytes()).await?;
+        dest.write_all(&self.to_bytes(padding)?).await?;
+        source
+            .seek(SeekFrom::Start(self.id3v2_len + self.metadata_len))
+            .await?;
+        tokio::io::copy(source, dest).await?;
+        Ok(())
+    }
+
+    /// Async counterpart of [`save_to_path`](Self::save_to_path)
+    pub async fn save_to_path_async(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .await?;
+        let loaded = Self::read_from_async(&mut tokio::io::BufReader::new(&mut file)).await?;
+        let tmp_path = match self.plan_save(path, &loaded)? {
+            Save::InPlace(bytes) => {
+                file.seek(SeekFrom::Start(0)).await?;
+                file.write_all(&bytes).await?;
+                return Ok(file.flush().await?);
+            }
+            Save::Rewrite(tmp_path) => tmp_path,
+        };
+        let result = async {
+            let mut tmp = tokio::io::BufWriter::new(tokio::fs::File::create(&tmp_path).await?);
+            self.write_to_async(&mut file, &mut tmp).await?;
+            tmp.flush().await?;
+            let tmp = tmp.into_inner();
+            tmp.set_permissions(file.metadata().await?.permissions())
+                .await?;
+            tmp.sync_all().await?;
+            drop(file);
+            tokio::fs::rename(&tmp_path, path).await?;
+            Ok(())
+        }
+        .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::VorbisComment;
+
+    fn sample_file(padding: u32) -> Vec<u8> {
+        let mut editor = MetadataEditor::new(StreamInfo {
+            sample_rate: 44100,
+            channels: 2,
+            bps: 16,
+            ..Default::default()
+        });
+        editor.push(VorbisComment::new("rotic"));
+        let mut bytes = editor.to_bytes(Some(padding)).unwrap();
+        bytes.extend_from_slice(&[0xFF, 0xF8, 1, 2, 3, 4]);
+        bytes
+    }
+
+    #[test]
+    fn save_in_place_and_rewrite() {
+        let path = std::env::temp_dir().join(format!("rotic-editor-{}.flac", std::process::id()));
+        let original = sample_file(64);
+        std::fs::write(&path, &original).unwrap();
+
+        let mut editor = MetadataEditor::open(&path).unwrap();
+        let mut comment: VorbisComment = editor.find().unwrap().unwrap();
+        comment.add("TITLE", "short");
+        editor.set(comment);
+        assert!(editor.fits_in_place());
+        editor.save_to_path(&path).unwrap();
+        let saved = std::fs::read(&path).unwrap();
+        assert_eq!(saved.len(), original.len());
+        assert_eq!(saved[saved.len() - 6..], original[original.len() - 6..]);
+
+        let mut editor = MetadataEditor::open(&path).unwrap();
+        let mut comment: VorbisComment = editor.find().unwrap().unwrap();
+        assert_eq!(comment.title(), Some("short"));
+        comment.add("LYRICS", "x".repeat(200));
+        editor.set(comment);
+        assert!(!editor.fits_in_place());
+        #[cfg(unix)]
+        use std::os::unix::fs::PermissionsExt;
+        #[cfg(unix)]
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        editor.save_to_path(&path).unwrap();
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        let saved = std::fs::read(&path).unwrap();
+        assert!(saved.ends_with(&[0xFF, 0xF8, 1, 2, 3, 4]));
+        let editor = MetadataEditor::open(&path).unwrap();
+        assert_eq!(editor.metadata_len() as usize, saved.len() - 6);
+        let padding: Padding = editor.find().unwrap().unwrap();
+        assert_eq!(padding.len, MetadataEditor::DEFAULT_PADDING as usize);
+        assert!(editor.blocks().last().unwrap().last_metadata_block());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
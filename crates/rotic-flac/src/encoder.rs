@@ -0,0 +1,365 @@
+use std::io::{Cursor, Seek, SeekFrom, Write};
+
+use md5::{Digest, Md5};
+
+use crate::error::Error::*;
+use crate::frame::{encode_frame, EncodeParams, StereoMode};
+use crate::metadata::{ConvertBytes, SeekPoint, SeekTable, StreamInfo, VorbisComment};
+use crate::verify::update_md5;
+use crate::{MetadataEditor, Result};
+
+/// Encoder settings, the defaults follow the `flac` command line tool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncoderConfig {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+    /// Samples per channel in every frame but the last
+    pub block_size: u16,
+    /// 0 restricts the encoder to fixed predictors
+    pub max_lpc_order: u8,
+    /// Precision of the quantized LPC coefficients, 0 picks one from the block size
+    pub qlp_precision: u8,
+    pub min_partition_order: u8,
+    pub max_partition_order: u8,
+    pub stereo: StereoMode,
+    /// Try every LPC order instead of the estimated best one
+    pub exhaustive_model_search: bool,
+    /// Expected number of samples per channel, required to reserve the SEEKTABLE
+    pub total_samples: Option<u64>,
+    /// Seconds between seek points, 0 disables the SEEKTABLE
+    pub seek_point_interval: u32,
+    pub padding: u32,
+}
+
+impl EncoderConfig {
+    pub const DEFAULT_LEVEL: u8 = 5;
+
+    pub fn new(sample_rate: u32, channels: u8, bits_per_sample: u8) -> EncoderConfig {
+        Self {
+            sample_rate,
+            channels,
+            bits_per_sample,
+            block_size: 4096,
+            max_lpc_order: 8,
+            qlp_precision: 0,
+            min_partition_order: 0,
+            max_partition_order: 5,
+            stereo: StereoMode::Exhaustive,
+            exhaustive_model_search: false,
+            total_samples: None,
+            seek_point_interval: 10,
+            padding: MetadataEditor::DEFAULT_PADDING,
+        }
+        .level(Self::DEFAULT_LEVEL)
+    }
+    pub fn from_stream_info(stream_info: &StreamInfo) -> EncoderConfig {
+        let mut config = Self::new(
+            stream_info.sample_rate,
+            stream_info.channels,
+            stream_info.bps,
+        );
+        if stream_info.max_block_size >= 16 {
+            config.block_size = stream_info.max_block_size;
+        }
+        if stream_info.total_samples > 0 {
+            config.total_samples = Some(stream_info.total_samples);
+        }
+        config
+    }
+    /// Applies the settings of `flac -0` to `flac -8`, levels above 8 are treated as 8
+    pub fn level(mut self, level: u8) -> EncoderConfig {
+        let (block_size, max_lpc_order, max_partition_order, stereo) = match level {
+            0 => (1152, 0, 3, StereoMode::Independent),
+            1 => (1152, 0, 3, StereoMode::Adaptive),
+            2 => (1152, 0, 3, StereoMode::Exhaustive),
+            3 => (4096, 6, 4, StereoMode::Independent),
+            4 => (4096, 8, 4, StereoMode::Adaptive),
+            5 => (4096, 8, 5, StereoMode::Exhaustive),
+            6 => (4096, 8, 6, StereoMode::Exhaustive),
+            _ => (4096, 12, 6, StereoMode::Exhaustive),
+        };
+        self.block_size = block_size;
+        self.max_lpc_order = max_lpc_order;
+        self.max_partition_order = max_partition_order;
+        self.stereo = stereo;
+        self.exhaustive_model_search = level >= 8;
+        self
+    }
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Err(Custom(format!("invalid encoder config: {reason}")));
+        match self {
+            c if !(1..=8).contains(&c.channels) => invalid("channels must be 1 to 8"),
+            c if !(4..=32).contains(&c.bits_per_sample) => invalid("bits per sample must be 4 to 32"),
+            c if c.sample_rate == 0 || c.sample_rate >= 1 << 20 => invalid("unsupported sample rate"),
+            c if c.block_size < 16 => invalid("block size must be at least 16"),
+            c if c.max_lpc_order > 32 => invalid("LPC order must be at most 32"),
+            c if c.qlp_precision > 15 => invalid("LPC precision must be at most 15"),
+            c if c.max_partition_order > 15 || c.min_partition_order > c.max_partition_order => {
+                invalid("invalid partition order range")
+            }
+            _ => Ok(()),
+        }
+    }
+    fn params(&self) -> EncodeParams {
+        let qlp_precision = match (self.qlp_precision, self.block_size) {
+            (0, ..=192) => 7,
+            (0, ..=384) => 8,
+            (0, ..=576) => 9,
+            (0, ..=1152) => 10,
+            (0, ..=2304) => 11,
+            (0, ..=4608) => 12,
+            (0, _) => 13,
+            (precision, _) => precision,
+        };
+        EncodeParams {
+            max_lpc_order: self.max_lpc_order as usize,
+            qlp_precision: qlp_precision as u32,
+            min_partition_order: self.min_partition_order as u32,
+            max_partition_order: self.max_partition_order as u32,
+            stereo: self.stereo,
+            exhaustive_model_search: self.exhaustive_model_search,
+        }
+    }
+}
+
+/// Writes a FLAC stream, STREAMINFO and SEEKTABLE are filled in by [`Encoder::finish`]
+pub struct Encoder<W: Write + Seek> {
+    writer: W,
+    config: EncoderConfig,
+    params: EncodeParams,
+    /// Position of `fLaC` in the writer
+    start: u64,
+    audio_start: u64,
+    pending: Vec<Vec<i64>>,
+    md5: Md5,
+    frame_number: u64,
+    total_samples: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    /// Filled seek points and the number of points reserved in the SEEKTABLE
+    seek_points: Vec<SeekPoint>,
+    reserved_points: usize,
+    next_target: u64,
+}
+
+impl<W: Write + Seek> Encoder<W> {
+    pub fn new(mut writer: W, config: EncoderConfig) -> Result<Encoder<W>> {
+        config.validate()?;
+        let start = writer.stream_position()?;
+        let spacing = config.seek_point_interval as u64 * config.sample_rate as u64;
+        let reserved_points = match config.total_samples {
+            Some(total) if spacing > 0 => total.div_ceil(spacing).max(1) as usize,
+            _ => 0,
+        };
+
+        let mut editor = MetadataEditor::new(StreamInfo {
+            min_block_size: config.block_size,
+            max_block_size: config.block_size,
+            sample_rate: config.sample_rate,
+            channels: config.channels,
+            bps: config.bits_per_sample,
+            ..Default::default()
+        });
+        if reserved_points > 0 {
+            let mut seek_table = SeekTable::new();
+            seek_table.push_placeholders(reserved_points);
+            editor.push(seek_table);
+        }
+        editor.push(VorbisComment::new(concat!("rotic-flac ", env!("CARGO_PKG_VERSION"))));
+        let metadata = editor.to_bytes((config.padding > 0).then_some(config.padding))?;
+        writer.write_all(&metadata)?;
+
+        Ok(Self {
+            params: config.params(),
+            pending: vec![Vec::with_capacity(config.block_size as usize); config.channels as usize],
+            writer,
+            config,
+            start,
+            audio_start: start + metadata.len() as u64,
+            md5: Md5::new(),
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_size: u32::MAX,
+            max_frame_size: 0,
+            seek_points: Vec::new(),
+            reserved_points,
+            next_target: 0,
+        })
+    }
+
+    /// Encodes interleaved samples, which must fit in the configured bits per sample
+    pub fn write(&mut self, samples: &[i32]) -> Result<()> {
+        let channels = self.config.channels as usize;
+        if !samples.len().is_multiple_of(channels) {
+            return Err(Custom("sample count is not a multiple of the channel count".to_owned()));
+        }
+        let bps = self.config.bits_per_sample as u32;
+        let (min, max) = (-(1i64 << (bps - 1)), (1i64 << (bps - 1)) - 1);
+        if let Some(s) = samples.iter().find(|s| !(min..=max).contains(&(**s as i64))) {
+            return Err(Custom(format!("sample {s} doesn't fit in {bps} bits")));
+        }
+        update_md5(&mut self.md5, samples, bps as u8);
+        for frame in samples.chunks_exact(channels) {
+            for (ch, sample) in frame.iter().enumerate() {
+                self.pending[ch].push(*sample as i64);
+            }
+            if self.pending[0].len() == self.config.block_size as usize {
+                self.write_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self) -> Result<()> {
+        let block_size = self.pending[0].len() as u64;
+        let frame = encode_frame(
+            &self.params,
+            &self.pending,
+            self.config.bits_per_sample as u32,
+            self.config.sample_rate,
+            self.frame_number,
+        );
+        let offset = self.writer.stream_position()? - self.audio_start;
+        self.writer.write_all(&frame)?;
+
+        let first = self.total_samples;
+        let spacing = self.config.seek_point_interval as u64 * self.config.sample_rate as u64;
+        while self.seek_points.len() < self.reserved_points && self.next_target < first + block_size {
+            if self.seek_points.last().is_none_or(|p| p.sample_number != first) {
+                self.seek_points.push(SeekPoint::new(first, offset, block_size as u16));
+            }
+            self.next_target += spacing;
+        }
+        self.min_frame_size = self.min_frame_size.min(frame.len() as u32);
+        self.max_frame_size = self.max_frame_size.max(frame.len() as u32);
+        self.total_samples += block_size;
+        self.frame_number += 1;
+        self.pending.iter_mut().for_each(|ch| ch.clear());
+        Ok(())
+    }
+
+    /// Encodes the remaining samples and fills in STREAMINFO and SEEKTABLE
+    pub fn finish(mut self) -> Result<W> {
+        if !self.pending[0].is_empty() {
+            self.write_frame()?;
+        }
+        let end = self.writer.stream_position()?;
+        let stream_info = StreamInfo {
+            min_block_size: self.config.block_size,
+            max_block_size: self.config.block_size,
+            min_frame_size: match self.frame_number {
+                0 => 0,
+                _ => self.min_frame_size,
+            },
+            max_frame_size: self.max_frame_size,
+            sample_rate: self.config.sample_rate,
+            channels: self.config.channels,
+            bps: self.config.bits_per_sample,
+            total_samples: self.total_samples,
+            md5: self.md5.finalize().into(),
+        };
+        self.writer.seek(SeekFrom::Start(self.start + 8))?;
+        self.writer.write_all(&stream_info.into_bytes())?;
+        if self.reserved_points > 0 {
+            let mut seek_table = SeekTable::new();
+            self.seek_points.iter().for_each(|p| seek_table.push(*p));
+            seek_table.push_placeholders(self.reserved_points - self.seek_points.len());
+            self.writer.seek(SeekFrom::Start(self.start + 8 + 34 + 4))?;
+            self.writer.write_all(&seek_table.into_bytes())?;
+        }
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Encodes interleaved samples into an in-memory FLAC stream
+pub fn encode(samples: &[i32], mut config: EncoderConfig) -> Result<Vec<u8>> {
+    config.total_samples = Some((samples.len() / config.channels.max(1) as usize) as u64);
+    let mut encoder = Encoder::new(Cursor::new(Vec::new()), config)?;
+    encoder.write(samples)?;
+    Ok(encoder.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Decoder;
+
+    fn signal(len: usize, channels: usize, bps: u32, seed: u64) -> Vec<i32> {
+        let mut state = seed;
+        let amplitude = ((1i64 << (bps - 1)) - 1) as f64;
+        (0..len * channels)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                let t = (i / channels) as f64;
+                let ch = (i % channels) as f64;
+                let tone = (t * (0.01 + ch * 0.003)).sin() * 0.6 + (t * 0.173).sin() * 0.2;
+                let noise = (state % 2001) as f64 / 1000.0 - 1.0;
+                ((tone + noise * 0.05) * amplitude) as i32
+            })
+            .collect()
+    }
+
+    fn decode_all(bytes: &[u8]) -> (StreamInfo, Vec<i32>) {
+        let decoder = Decoder::new(bytes).unwrap();
+        let stream_info = decoder.stream_info().clone();
+        let samples = decoder.flat_map(|b| b.unwrap().into_samples()).collect();
+        (stream_info, samples)
+    }
+
+    #[test]
+    fn round_trip_levels() {
+        let input = signal(10_000, 2, 16, 1);
+        for level in 0..=8 {
+            let mut config = EncoderConfig::new(44100, 2, 16).level(level);
+            config.padding = 0;
+            let bytes = encode(&input, config).unwrap();
+            let (stream_info, output) = decode_all(&bytes);
+            assert_eq!(output, input, "level {level}");
+            assert_eq!(stream_info.total_samples, 10_000);
+            assert!(bytes.len() < input.len() * 13 / 8, "level {level} didn't compress");
+
+            let mut md5 = Md5::new();
+            output.iter().for_each(|s| md5.update((*s as i16).to_le_bytes()));
+            assert_eq!(stream_info.md5, <[u8; 16]>::from(md5.finalize()));
+        }
+    }
+
+    #[test]
+    fn round_trip_formats() {
+        for (channels, bps, rate, block_size) in [
+            (1, 8, 8000, 1000),
+            (3, 12, 22050, 4096),
+            (2, 20, 96000, 576),
+            (2, 24, 48000, 4096),
+            (6, 24, 12345, 192),
+            (2, 32, 44100, 1152),
+            (1, 4, 44100, 17),
+        ] {
+            let input = signal(5000, channels, bps, bps as u64);
+            let mut config = EncoderConfig::new(rate, channels as u8, bps as u8);
+            config.block_size = block_size;
+            let (stream_info, output) = decode_all(&encode(&input, config).unwrap());
+            assert_eq!(output, input, "{channels} channels, {bps} bits");
+            assert_eq!(stream_info.sample_rate, rate);
+        }
+        let silence = vec![0; 4096 * 2];
+        let (_, output) = decode_all(&encode(&silence, EncoderConfig::new(44100, 2, 16)).unwrap());
+        assert_eq!(output, silence);
+    }
+
+    #[test]
+    fn seek_table() {
+        let input = signal(44100 * 25, 1, 16, 7);
+        let bytes = encode(&input, EncoderConfig::new(44100, 1, 16).level(0)).unwrap();
+        let decoder = Decoder::new(&bytes[..]).unwrap();
+        let seek_table: SeekTable = decoder.find_meta().unwrap().unwrap();
+        let samples: Vec<u64> = seek_table.seek_points().map(|p| p.sample_number).collect();
+        assert_eq!(samples, [0, 440064, 881280]);
+        assert!(decoder.blocks()[1].is::<SeekTable>());
+    }
+}
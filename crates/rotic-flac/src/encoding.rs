@@ -0,0 +1,179 @@
+//! Vorbis comments written in legacy encodings
+//!
+//! Comments are UTF-8, but old taggers wrote them in the code page of the system, e.g. GBK or
+//! Shift-JIS. [`VorbisComment::from_bytes`](crate::metadata::ConvertBytes::from_bytes)
+//! replaces such bytes with U+FFFD, a [`DecodingPolicy`] tries legacy encodings instead.
+
+pub use encoding_rs::{self, Encoding};
+use encoding_rs::{GBK, SHIFT_JIS, WINDOWS_1252};
+
+use crate::metadata::{BorrowBytes, VorbisComment, VorbisCommentRef};
+use crate::{MetadataEditor, Result};
+
+/// Legacy encodings to try, in order, for a VORBIS_COMMENT block that isn't valid UTF-8.
+///
+/// One encoding is picked for the whole block: the first one decoding every string that isn't
+/// UTF-8 without errors. Many byte strings are valid in several encodings, e.g. most Shift-JIS
+/// text also decodes as GBK, so the order matters. Single-byte encodings like windows-1252
+/// accept anything and belong at the end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodingPolicy {
+    encodings: Vec<&'static Encoding>,
+}
+
+impl Default for DecodingPolicy {
+    /// GBK, Shift-JIS, then windows-1252 (Latin-1)
+    fn default() -> Self {
+        Self::new([GBK, SHIFT_JIS, WINDOWS_1252])
+    }
+}
+
+impl DecodingPolicy {
+    pub fn new(encodings: impl IntoIterator<Item = &'static Encoding>) -> DecodingPolicy {
+        Self {
+            encodings: encodings.into_iter().collect(),
+        }
+    }
+    pub fn encodings(&self) -> &[&'static Encoding] {
+        &self.encodings
+    }
+    /// Encoding of the strings that aren't UTF-8, `None` if all of them are or no encoding
+    /// of the list fits
+    pub fn detect<'a>(
+        &self,
+        texts: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Option<&'static Encoding> {
+        let invalid: Vec<_> = texts
+            .into_iter()
+            .filter(|text| std::str::from_utf8(text).is_err())
+            .collect();
+        if invalid.is_empty() {
+            return None;
+        }
+        self.encodings.iter().copied().find(|encoding| {
+            invalid.iter().all(|text| {
+                encoding
+                    .decode_without_bom_handling_and_without_replacement(text)
+                    .is_some()
+            })
+        })
+    }
+    /// Parses a VORBIS_COMMENT block, returning the legacy encoding its text was decoded
+    /// from. Without one, strings that aren't UTF-8 are decoded lossily as usual
+    pub fn decode(&self, buf: Vec<u8>) -> Result<(VorbisComment, Option<&'static Encoding>)> {
+        let encoding = self.detect(VorbisCommentRef::from_slice(&buf)?.raw_texts());
+        let comment = VorbisComment::decode(buf, |text| match std::str::from_utf8(text) {
+            Ok(text) => text.to_owned(),
+            Err(_) => match encoding {
+                Some(encoding) => encoding.decode_without_bom_handling(text).0.into_owned(),
+                None => String::from_utf8_lossy(text).into_owned(),
+            },
+        });
+        Ok((comment?, encoding))
+    }
+}
+
+impl VorbisComment {
+    /// Like [`from_bytes`](crate::metadata::ConvertBytes::from_bytes), decoding text that
+    /// isn't UTF-8 with the policy. Returns the legacy encoding used, if any
+    pub fn from_bytes_with(
+        buf: Vec<u8>,
+        policy: &DecodingPolicy,
+    ) -> Result<(VorbisComment, Option<&'static Encoding>)> {
+        policy.decode(buf)
+    }
+}
+
+impl MetadataEditor {
+    /// Re-decodes the VORBIS_COMMENT block with the policy and, if it was written in a
+    /// legacy encoding, replaces it so it is saved as UTF-8. Returns that encoding
+    pub fn repair_encoding(
+        &mut self,
+        policy: &DecodingPolicy,
+    ) -> Result<Option<&'static Encoding>> {
+        let Some(block) = self.blocks().iter().find(|b| b.is::<VorbisComment>()) else {
+            return Ok(None);
+        };
+        let (comment, encoding) = policy.decode(block.block_data().clone())?;
+        if encoding.is_some() {
+            self.set(comment);
+        }
+        Ok(encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{Block, ConvertBytes, StreamInfo};
+
+    fn block(vendor: &[u8], fields: &[&[u8]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for text in std::iter::once(&vendor).chain(fields) {
+            buf.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            buf.extend_from_slice(text);
+            if buf.len() == 4 + vendor.len() {
+                buf.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    fn field(key: &str, value: &str, encoding: &'static Encoding) -> Vec<u8> {
+        let mut field = format!("{key}=").into_bytes();
+        field.extend_from_slice(&encoding.encode(value).0);
+        field
+    }
+
+    #[test]
+    fn legacy_encodings() {
+        let policy = DecodingPolicy::default();
+        let gbk = block(
+            b"old tagger",
+            &[&field("TITLE", "\u{4e2d}\u{6587}", GBK), b"ARTIST=A"],
+        );
+        let (comment, encoding) = VorbisComment::from_bytes_with(gbk.clone(), &policy).unwrap();
+        assert_eq!(encoding, Some(GBK));
+        assert_eq!(comment.title(), Some("\u{4e2d}\u{6587}"));
+        assert_eq!(comment.artist(), Some("A"));
+        assert_eq!(comment.raw_vorbis_comment(), gbk);
+
+        let latin1 = block(
+            b"vendor",
+            &[&field("TITLE", "Caf\u{e9} au lait", WINDOWS_1252)],
+        );
+        let (comment, encoding) = policy.decode(latin1.clone()).unwrap();
+        assert_eq!(encoding, Some(WINDOWS_1252));
+        assert_eq!(comment.title(), Some("Caf\u{e9} au lait"));
+        let (comment, encoding) = DecodingPolicy::new([GBK]).decode(latin1).unwrap();
+        assert_eq!(encoding, None);
+        assert_eq!(comment.title(), Some("Caf\u{fffd} au lait"));
+
+        // valid GBK as well, only the order of the list tells them apart
+        let sjis = block(
+            b"vendor",
+            &[&field("TITLE", "\u{30c6}\u{30b9}\u{30c8}", SHIFT_JIS)],
+        );
+        let (comment, encoding) = DecodingPolicy::new([SHIFT_JIS, GBK]).decode(sjis).unwrap();
+        assert_eq!(encoding, Some(SHIFT_JIS));
+        assert_eq!(comment.title(), Some("\u{30c6}\u{30b9}\u{30c8}"));
+
+        let utf8 = VorbisComment::new("rotic").into_bytes();
+        assert_eq!(policy.decode(utf8).unwrap().1, None);
+
+        let mut editor = MetadataEditor::new(StreamInfo {
+            sample_rate: 44100,
+            channels: 2,
+            bps: 16,
+            ..Default::default()
+        });
+        assert_eq!(editor.repair_encoding(&policy).unwrap(), None);
+        let size = gbk.len() as u32;
+        editor.blocks_mut().push(Block::new(false, 4, size, gbk));
+        assert_eq!(editor.repair_encoding(&policy).unwrap(), Some(GBK));
+        let saved = MetadataEditor::read_from(&mut &editor.to_bytes(None).unwrap()[..]).unwrap();
+        let comment: VorbisComment = saved.find().unwrap().unwrap();
+        assert_eq!(comment.title(), Some("\u{4e2d}\u{6587}"));
+        assert_eq!(saved.clone().repair_encoding(&policy).unwrap(), None);
+    }
+}
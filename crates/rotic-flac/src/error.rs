@@ -1,22 +1,197 @@
+use std::fmt;
+
+use crate::metadata::block_type_name;
+
+/// Errors returned by rotic-flac, offsets count bytes from the `fLaC` marker unless noted
 #[derive(Debug)]
 pub enum Error {
     InvalidFormat,
     #[allow(clippy::enum_variant_names)]
     IoError(std::io::Error),
     Custom(String),
+    /// The stream doesn't start with `fLaC`, after an optional ID3v2 tag
+    BadMagic([u8; 4]),
+    /// Data starting at `offset` is shorter than the length declared for it
+    Truncated {
+        offset: u64,
+        expected: u64,
+        actual: u64,
+    },
+    UnknownPictureType(u32),
+    /// A text field which has to be UTF-8 isn't
+    InvalidUtf8 {
+        field: &'static str,
+    },
+    /// The CRC of the frame at `offset` doesn't match, `frame` is the frame number
+    /// or, with variable block sizes, the number of its first sample
+    CrcMismatch {
+        frame: u64,
+        offset: u64,
+    },
+    /// The checksum of the Ogg page at `offset` doesn't match, offsets count bytes from the
+    /// start of the Ogg stream
+    PageCrcMismatch {
+        page: u32,
+        offset: u64,
+    },
+    /// A reserved value or a feature the crate doesn't support
+    Unsupported(&'static str),
+    /// A STREAMINFO field breaks a constraint of the specification
+    InvalidStreamInfo(&'static str),
+    /// Parsing a metadata block failed, offsets in `source` count from the start of its payload
+    Block {
+        block_type: u8,
+        /// Offset of the payload, if known
+        offset: Option<u64>,
+        source: Box<Error>,
+    },
 }
-impl std::error::Error for Error {}
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+
+impl Error {
+    /// Makes an offset relative to `base` absolute
+    pub(crate) fn offset_by(self, base: u64) -> Error {
+        match self {
+            Error::Truncated {
+                offset,
+                expected,
+                actual,
+            } => Error::Truncated {
+                offset: base + offset,
+                expected,
+                actual,
+            },
+            Error::CrcMismatch { frame, offset } => Error::CrcMismatch {
+                frame,
+                offset: base + offset,
+            },
+            err => err,
+        }
+    }
+    pub(crate) fn in_block(self, block_type: u8, offset: Option<u64>) -> Error {
+        Error::Block {
+            block_type,
+            offset,
+            source: Box::new(self),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IoError(err) => Some(err),
+            Error::Block { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::InvalidFormat => f.write_str("Invalid format"),
             Error::Custom(err) => f.write_str(err),
-            Error::IoError(err) => std::fmt::Display::fmt(err, f),
+            Error::IoError(err) => fmt::Display::fmt(err, f),
+            Error::BadMagic(found) => write!(f, "expected `fLaC`, found {found:02X?}"),
+            Error::Truncated {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "data at byte {offset} is truncated: expected {expected} bytes, found {actual}"
+            ),
+            Error::UnknownPictureType(ty) => write!(f, "unknown picture type {ty}"),
+            Error::InvalidUtf8 { field } => write!(f, "{field} is not valid UTF-8"),
+            Error::CrcMismatch { frame, offset } => {
+                write!(f, "CRC mismatch in frame {frame} at byte {offset}")
+            }
+            Error::PageCrcMismatch { page, offset } => {
+                write!(f, "CRC mismatch in Ogg page {page} at byte {offset}")
+            }
+            Error::Unsupported(what) => write!(f, "unsupported: {what}"),
+            Error::InvalidStreamInfo(what) => write!(f, "invalid STREAMINFO: {what}"),
+            Error::Block {
+                block_type,
+                offset: Some(offset),
+                source,
+            } => write!(
+                f,
+                "{} block at byte {offset}: {source}",
+                block_type_name(*block_type)
+            ),
+            Error::Block {
+                block_type, source, ..
+            } => write!(f, "{} block: {source}", block_type_name(*block_type)),
         }
     }
 }
+
 impl From<std::io::Error> for Error {
     fn from(value: std::io::Error) -> Self {
-        Self::IoError(value)
+        // errors of readers like `OggFlacReader` come back wrapped in an `io::Error`
+        match value.downcast::<Error>() {
+            Ok(err) => err,
+            Err(err) => Self::IoError(err),
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{Block, Picture, StreamInfo};
+    use crate::{encode, read_from_bytes, Decoder, EncoderConfig, MetadataEditor, MetadataReader};
+
+    #[test]
+    fn positioned_errors() {
+        assert!(matches!(read_from_bytes(b"RIFF"), Err(Error::BadMagic(m)) if &m == b"RIFF"));
+
+        let mut editor = MetadataEditor::new(StreamInfo {
+            sample_rate: 8000,
+            channels: 1,
+            bps: 8,
+            ..Default::default()
+        });
+        let picture = [0, 0, 1, 0, 0, 0, 0, 0].to_vec();
+        editor.blocks_mut().push(Block::new(false, 6, 8, picture));
+        let bytes = editor.to_bytes(None).unwrap();
+
+        let err = read_from_bytes(&bytes[..bytes.len() - 3]).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Truncated {
+                offset: 46,
+                expected: 8,
+                actual: 5
+            }
+        ));
+        let mut reader = MetadataReader::new(&bytes[..]).unwrap();
+        reader.nth(1).unwrap().unwrap();
+        let err = reader.read_block::<Picture>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "PICTURE block at byte 46: unknown picture type 256"
+        );
+
+        let samples: Vec<i32> = (0..5000).map(|i| i % 200 - 100).collect();
+        let mut config = EncoderConfig::new(8000, 1, 8);
+        config.padding = 0;
+        let mut bytes = encode(&samples, config).unwrap();
+        let audio_offset = crate::decoder::metadata_len(&read_from_bytes(&bytes).unwrap());
+        *bytes.last_mut().unwrap() ^= 1;
+        let mut decoder = Decoder::new(&bytes[..]).unwrap();
+        decoder.next().unwrap().unwrap();
+        let Some(Err(Error::CrcMismatch { frame: 1, offset })) = decoder.next() else {
+            panic!("expected a CRC mismatch");
+        };
+        // the header CRC-8 is checked before the frame is decoded
+        bytes[offset as usize + 5] ^= 1;
+        let mut decoder = Decoder::new(&bytes[..]).unwrap();
+        decoder.next().unwrap().unwrap();
+        assert!(
+            matches!(decoder.next(), Some(Err(Error::CrcMismatch { frame: 1, offset: o })) if o == offset)
+        );
+        assert!(offset > audio_offset);
+    }
+}
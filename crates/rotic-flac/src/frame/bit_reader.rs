@@ -0,0 +1,92 @@
+use std::io;
+
+use crate::error::Error;
+use crate::Result;
+
+pub(crate) fn unexpected_eof() -> Error {
+    Error::IoError(io::ErrorKind::UnexpectedEof.into())
+}
+
+/// Whether decoding failed only because the buffer ended too early
+pub(crate) fn is_eof(err: &Error) -> bool {
+    matches!(err, Error::IoError(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+/// MSB-first bit reader over an in-memory frame
+pub(crate) struct BitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(buf: &'a [u8], pos: usize) -> BitReader<'a> {
+        Self { buf, pos, bit: 0 }
+    }
+    /// Position of the next whole byte
+    pub(crate) fn byte_pos(&self) -> usize {
+        self.pos + (self.bit != 0) as usize
+    }
+    pub(crate) fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+    pub(crate) fn read_bits(&mut self, mut n: u32) -> Result<u64> {
+        let mut value = 0u64;
+        while n > 0 {
+            let byte = *self.buf.get(self.pos).ok_or_else(unexpected_eof)?;
+            let avail = 8 - self.bit;
+            let take = avail.min(n);
+            let bits = ((byte as u32) >> (avail - take)) & ((1 << take) - 1);
+            value = (value << take) | bits as u64;
+            n -= take;
+            self.bit += take;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+    pub(crate) fn read_bit(&mut self) -> Result<bool> {
+        Ok(self.read_bits(1)? == 1)
+    }
+    /// Reads a two's complement value of `n` bits
+    pub(crate) fn read_signed(&mut self, n: u32) -> Result<i64> {
+        if n == 0 {
+            return Ok(0);
+        }
+        let value = self.read_bits(n)?;
+        Ok(((value << (64 - n)) as i64) >> (64 - n))
+    }
+    /// Counts zero bits up to and including the next one bit
+    pub(crate) fn read_unary(&mut self) -> Result<u32> {
+        let mut count = 0;
+        loop {
+            let byte = *self.buf.get(self.pos).ok_or_else(unexpected_eof)?;
+            let rest = ((byte as u32) << self.bit) & 0xFF;
+            if rest == 0 {
+                count += 8 - self.bit;
+                self.bit = 0;
+                self.pos += 1;
+                continue;
+            }
+            let zeros = rest.leading_zeros() - 24;
+            count += zeros;
+            self.bit += zeros + 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+            return Ok(count);
+        }
+    }
+    /// Reads a Rice coded, zigzag mapped residual
+    pub(crate) fn read_rice(&mut self, param: u32) -> Result<i64> {
+        let high = self.read_unary()? as u64;
+        let value = (high << param) | self.read_bits(param)?;
+        Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+    }
+}
@@ -0,0 +1,70 @@
+/// MSB-first bit writer used to build frames
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    pub(crate) fn new() -> BitWriter {
+        Self::default()
+    }
+    /// Number of bits written so far
+    pub(crate) fn len(&self) -> usize {
+        self.bytes.len() * 8 + self.bits as usize
+    }
+    pub(crate) fn write_bits(&mut self, value: u64, n: u32) {
+        if n > 32 {
+            self.write_bits(value >> 32, n - 32);
+            return self.write_bits(value & 0xFFFF_FFFF, 32);
+        }
+        if n == 0 {
+            return;
+        }
+        self.acc = (self.acc << n) | (value & ((1 << n) - 1));
+        self.bits += n;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+    }
+    pub(crate) fn write_signed(&mut self, value: i64, n: u32) {
+        self.write_bits(value as u64, n);
+    }
+    pub(crate) fn write_unary(&mut self, mut zeros: u64) {
+        while zeros > 32 {
+            self.write_bits(0, 32);
+            zeros -= 32;
+        }
+        self.write_bits(1, zeros as u32 + 1);
+    }
+    /// Writes a zigzag mapped residual with Rice parameter `param`
+    pub(crate) fn write_rice(&mut self, value: i64, param: u32) {
+        let folded = ((value << 1) ^ (value >> 63)) as u64;
+        self.write_unary(folded >> param);
+        self.write_bits(folded, param);
+    }
+    pub(crate) fn align(&mut self) {
+        if self.bits > 0 {
+            self.write_bits(0, 8 - self.bits);
+        }
+    }
+    pub(crate) fn append(&mut self, other: &BitWriter) {
+        if self.bits == 0 {
+            self.bytes.extend_from_slice(&other.bytes);
+        } else {
+            other.bytes.iter().for_each(|b| self.write_bits(*b as u64, 8));
+        }
+        self.write_bits(other.acc, other.bits);
+    }
+    /// Bytes written so far, the writer must be byte aligned
+    pub(crate) fn bytes(&self) -> &[u8] {
+        debug_assert_eq!(self.bits, 0);
+        &self.bytes
+    }
+    pub(crate) fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
@@ -0,0 +1,260 @@
+use super::bit_reader::BitReader;
+use super::{ChannelAssignment, FrameHeader};
+use crate::crc::crc16;
+use crate::error::Error::*;
+use crate::metadata::StreamInfo;
+use crate::Result;
+
+/// Decoded samples of one frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioBlock {
+    header: FrameHeader,
+    first_sample: u64,
+    sample_rate: u32,
+    bits_per_sample: u8,
+    samples: Vec<i32>,
+}
+
+impl AudioBlock {
+    pub fn header(&self) -> &FrameHeader {
+        &self.header
+    }
+    /// Number of the first sample of the block within the stream
+    pub fn first_sample(&self) -> u64 {
+        self.first_sample
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    pub fn bits_per_sample(&self) -> u8 {
+        self.bits_per_sample
+    }
+    pub fn channels(&self) -> usize {
+        self.header.channels() as usize
+    }
+    /// Number of samples per channel
+    pub fn block_size(&self) -> usize {
+        self.samples.len() / self.channels()
+    }
+    /// Samples interleaved by channel
+    pub fn samples(&self) -> &[i32] {
+        &self.samples
+    }
+    pub fn into_samples(self) -> Vec<i32> {
+        self.samples
+    }
+    pub fn channel(&self, channel: usize) -> impl Iterator<Item = i32> + '_ {
+        self.samples
+            .iter()
+            .skip(channel)
+            .step_by(self.channels())
+            .copied()
+    }
+    /// Drops the first `count` samples of every channel
+    pub(crate) fn skip_samples(&mut self, count: usize) {
+        let count = count.min(self.block_size());
+        self.samples.drain(..count * self.channels());
+        self.first_sample += count as u64;
+    }
+}
+
+/// Result of decoding a single frame from a buffer
+pub(crate) struct DecodedFrame {
+    pub(crate) block: AudioBlock,
+    /// Bytes taken by the frame, footer included
+    pub(crate) len: usize,
+    pub(crate) crc_valid: bool,
+}
+
+/// Decodes the frame at the start of `buf`
+pub(crate) fn decode_frame(buf: &[u8], stream_info: &StreamInfo) -> Result<DecodedFrame> {
+    let header = FrameHeader::parse(buf)?;
+    let block_size = header.block_size() as usize;
+    let bps = header.bits_per_sample_or(stream_info) as u32;
+    let assignment = header.channel_assignment();
+    let mut reader = BitReader::new(buf, header.header_len());
+
+    let mut channels = Vec::with_capacity(assignment.channels() as usize);
+    for ch in 0..assignment.channels() {
+        let side = match assignment {
+            ChannelAssignment::Independent(_) => false,
+            ChannelAssignment::LeftSide | ChannelAssignment::MidSide => ch == 1,
+            ChannelAssignment::RightSide => ch == 0,
+        };
+        let mut samples = vec![0; block_size];
+        decode_subframe(&mut reader, bps + side as u32, &mut samples)?;
+        channels.push(samples);
+    }
+    reader.align();
+    let footer = reader.byte_pos();
+    let crc = reader.read_bits(16)? as u16;
+    let crc_valid = crc16(&buf[..footer]) == crc;
+
+    // the subframes are in range, so the decorrelation can't overflow
+    match assignment {
+        ChannelAssignment::Independent(_) => {}
+        ChannelAssignment::LeftSide => {
+            let (left, side) = channels.split_at_mut(1);
+            left[0].iter().zip(side[0].iter_mut()).for_each(|(l, s)| *s = *l - *s);
+        }
+        ChannelAssignment::RightSide => {
+            let (side, right) = channels.split_at_mut(1);
+            side[0].iter_mut().zip(right[0].iter()).for_each(|(s, r)| *s += *r);
+        }
+        ChannelAssignment::MidSide => {
+            let (mid, side) = channels.split_at_mut(1);
+            for (m, s) in mid[0].iter_mut().zip(side[0].iter_mut()) {
+                let mid = (*m << 1) | (*s & 1);
+                (*m, *s) = ((mid + *s) >> 1, (mid - *s) >> 1);
+            }
+        }
+    }
+    if channels.iter().flatten().any(|s| !fits(*s, bps)) {
+        return Err(InvalidFormat);
+    }
+    let mut samples = Vec::with_capacity(block_size * channels.len());
+    for i in 0..block_size {
+        samples.extend(channels.iter().map(|ch| ch[i] as i32));
+    }
+    let block = AudioBlock {
+        first_sample: header.first_sample(stream_info),
+        sample_rate: header.sample_rate_or(stream_info),
+        bits_per_sample: bps as u8,
+        header,
+        samples,
+    };
+    Ok(DecodedFrame {
+        block,
+        len: footer + 2,
+        crc_valid,
+    })
+}
+
+fn decode_subframe(reader: &mut BitReader, bps: u32, out: &mut [i64]) -> Result<()> {
+    if reader.read_bit()? {
+        return Err(InvalidFormat);
+    }
+    let ty = reader.read_bits(6)? as u32;
+    let wasted = match reader.read_bit()? {
+        true => reader.read_unary()? + 1,
+        false => 0,
+    };
+    if wasted >= bps {
+        return Err(InvalidFormat);
+    }
+    let bps = bps - wasted;
+    match ty {
+        0b000000 => {
+            let value = reader.read_signed(bps)?;
+            out.fill(value);
+        }
+        0b000001 => {
+            for sample in out.iter_mut() {
+                *sample = reader.read_signed(bps)?;
+            }
+        }
+        0b001000..=0b001100 => {
+            let order = (ty & 0b111) as usize;
+            read_warm_up(reader, bps, order, out)?;
+            read_residual(reader, order, out)?;
+            restore_fixed(order, out)?;
+        }
+        0b100000..=0b111111 => {
+            let order = (ty & 0b11111) as usize + 1;
+            read_warm_up(reader, bps, order, out)?;
+            let precision = reader.read_bits(4)? as u32 + 1;
+            if precision == 16 {
+                return Err(InvalidFormat);
+            }
+            let shift = reader.read_signed(5)?;
+            if shift < 0 {
+                return Err(InvalidFormat);
+            }
+            let mut coefs = Vec::with_capacity(order);
+            for _ in 0..order {
+                coefs.push(reader.read_signed(precision)?);
+            }
+            read_residual(reader, order, out)?;
+            restore_lpc(&coefs, shift as u32, out)?;
+        }
+        _ => return Err(Unsupported("reserved subframe type")),
+    }
+    if out.iter().any(|s| !fits(*s, bps)) {
+        return Err(InvalidFormat);
+    }
+    if wasted > 0 {
+        out.iter_mut().for_each(|s| *s <<= wasted);
+    }
+    Ok(())
+}
+
+fn read_warm_up(reader: &mut BitReader, bps: u32, order: usize, out: &mut [i64]) -> Result<()> {
+    if order > out.len() {
+        return Err(InvalidFormat);
+    }
+    for sample in &mut out[..order] {
+        *sample = reader.read_signed(bps)?;
+    }
+    Ok(())
+}
+
+/// Reads the partitioned Rice coded residual into `out[order..]`
+fn read_residual(reader: &mut BitReader, order: usize, out: &mut [i64]) -> Result<()> {
+    let param_bits = match reader.read_bits(2)? {
+        0b00 => 4,
+        0b01 => 5,
+        _ => return Err(Unsupported("reserved residual coding method")),
+    };
+    let escape = (1 << param_bits) - 1;
+    let partition_order = reader.read_bits(4)? as u32;
+    let partition_len = out.len() >> partition_order;
+    if partition_len << partition_order != out.len() || partition_len < order {
+        return Err(InvalidFormat);
+    }
+    let mut pos = order;
+    for partition in 0..1usize << partition_order {
+        let end = (partition + 1) * partition_len;
+        let param = reader.read_bits(param_bits)? as u32;
+        if param == escape {
+            let bits = reader.read_bits(5)? as u32;
+            for sample in &mut out[pos..end] {
+                *sample = reader.read_signed(bits)?;
+            }
+        } else {
+            for sample in &mut out[pos..end] {
+                *sample = reader.read_rice(param)?;
+            }
+        }
+        pos = end;
+    }
+    Ok(())
+}
+
+/// Predictors of the FIXED subframe orders, as LPC coefficients
+const FIXED_COEFS: [&[i64]; 5] = [&[], &[1], &[2, -1], &[3, -3, 1], &[4, -6, 4, -1]];
+
+fn restore_fixed(order: usize, out: &mut [i64]) -> Result<()> {
+    restore_lpc(FIXED_COEFS[order], 0, out)
+}
+
+/// Adds the prediction to the residual in `out[order..]`, failing if it overflows
+fn restore_lpc(coefs: &[i64], shift: u32, out: &mut [i64]) -> Result<()> {
+    let order = coefs.len();
+    for i in order..out.len() {
+        let prediction = coefs
+            .iter()
+            .zip(out[i - order..i].iter().rev())
+            .try_fold(0i64, |sum, (c, s)| sum.checked_add(c.checked_mul(*s)?))
+            .ok_or(InvalidFormat)?;
+        out[i] = out[i]
+            .checked_add(prediction >> shift)
+            .ok_or(InvalidFormat)?;
+    }
+    Ok(())
+}
+
+/// Whether `sample` fits in a signed `bps` bit integer
+fn fits(sample: i64, bps: u32) -> bool {
+    let limit = 1i64 << (bps - 1);
+    (-limit..limit).contains(&sample)
+}
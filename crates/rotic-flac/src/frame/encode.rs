@@ -0,0 +1,520 @@
+use super::bit_writer::BitWriter;
+use super::{ChannelAssignment, SampleRate};
+use crate::crc::{crc16, crc8_update};
+
+/// How the channels of a stereo stream are decorrelated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StereoMode {
+    /// Always code left and right independently
+    Independent,
+    /// Pick the channel assignment from a cheap estimate of the residual size
+    Adaptive,
+    /// Encode every channel assignment and keep the smallest
+    #[default]
+    Exhaustive,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct EncodeParams {
+    pub(crate) max_lpc_order: usize,
+    pub(crate) qlp_precision: u32,
+    pub(crate) min_partition_order: u32,
+    pub(crate) max_partition_order: u32,
+    pub(crate) stereo: StereoMode,
+    pub(crate) exhaustive_model_search: bool,
+}
+
+/// Largest fixed predictor order
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_RICE_PARAM: u32 = 30;
+
+/// Encodes one fixed blocking frame, `channels` holds one sample vector per channel
+pub(crate) fn encode_frame(
+    params: &EncodeParams,
+    channels: &[Vec<i64>],
+    bps: u32,
+    sample_rate: u32,
+    frame_number: u64,
+) -> Vec<u8> {
+    let block_size = channels[0].len();
+    let (assignment, subframes) = match channels {
+        [left, right] if params.stereo != StereoMode::Independent => {
+            encode_stereo(params, left, right, bps)
+        }
+        _ => (
+            ChannelAssignment::Independent(channels.len() as u8),
+            channels
+                .iter()
+                .map(|ch| encode_subframe(params, ch, bps))
+                .collect(),
+        ),
+    };
+
+    let mut frame = BitWriter::new();
+    write_header(&mut frame, block_size, sample_rate, assignment, bps, frame_number);
+    subframes.iter().for_each(|s| frame.append(s));
+    frame.align();
+    let crc = crc16(frame.bytes());
+    frame.write_bits(crc as u64, 16);
+    frame.into_bytes()
+}
+
+fn write_header(
+    frame: &mut BitWriter,
+    block_size: usize,
+    sample_rate: u32,
+    assignment: ChannelAssignment,
+    bps: u32,
+    frame_number: u64,
+) {
+    let (block_size_code, block_size_bits) = match block_size {
+        192 => (0b0001, 0),
+        576 | 1152 | 2304 | 4608 => (2 + (block_size / 576).trailing_zeros() as u64, 0),
+        256 | 512 | 1024 | 2048 | 4096 | 8192 | 16384 | 32768 => {
+            (8 + (block_size / 256).trailing_zeros() as u64, 0)
+        }
+        1..=256 => (0b0110, 8),
+        _ => (0b0111, 16),
+    };
+    let rate = sample_rate;
+    let (rate_code, rate_bits, rate_value) = match sample_rate_code(rate) {
+        Some(code) => (code, 0, 0),
+        None if rate.is_multiple_of(1000) && rate / 1000 <= 255 => (SampleRate::KHz8b, 8, rate / 1000),
+        None if rate <= 0xFFFF => (SampleRate::Hz16b, 16, rate),
+        None if rate.is_multiple_of(10) && rate / 10 <= 0xFFFF => (SampleRate::Hz16bTens, 16, rate / 10),
+        None => (SampleRate::FromMetaBlock, 0, 0),
+    };
+    let bps_code = match bps {
+        8 => 0b001,
+        12 => 0b010,
+        16 => 0b100,
+        20 => 0b101,
+        24 => 0b110,
+        32 => 0b111,
+        _ => 0b000,
+    };
+    let mut header = BitWriter::new();
+    header.write_bits(0xFFF8, 16);
+    header.write_bits(block_size_code, 4);
+    header.write_bits(rate_code.to_u8() as u64, 4);
+    header.write_bits(assignment.to_u8() as u64, 4);
+    header.write_bits(bps_code, 3);
+    header.write_bits(0, 1);
+    write_coded_number(&mut header, frame_number);
+    header.write_bits(block_size as u64 - 1, block_size_bits);
+    header.write_bits(rate_value as u64, rate_bits);
+    let crc = header.bytes().iter().fold(0, |crc, b| crc8_update(crc, *b));
+    header.write_bits(crc as u64, 8);
+    frame.append(&header);
+}
+
+fn sample_rate_code(rate: u32) -> Option<SampleRate> {
+    (1..=11)
+        .map(SampleRate::from_u8)
+        .find(|code| code.hz() == Some(rate))
+}
+
+/// Writes a frame or sample number with the "UTF-8" style coding
+fn write_coded_number(w: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        return w.write_bits(value, 8);
+    }
+    let bits = 64 - value.leading_zeros();
+    // every continuation byte carries 6 bits, the first byte 6 - extra
+    let extra = (1..=6).find(|n| bits <= 6 - n + 6 * n).unwrap_or(6);
+    let lead = (0xFF00u64 >> (extra + 1)) & 0xFF;
+    w.write_bits(lead | (value >> (6 * extra)), 8);
+    for i in (0..extra).rev() {
+        w.write_bits(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn encode_stereo(
+    params: &EncodeParams,
+    left: &[i64],
+    right: &[i64],
+    bps: u32,
+) -> (ChannelAssignment, Vec<BitWriter>) {
+    let side: Vec<i64> = left.iter().zip(right).map(|(l, r)| l - r).collect();
+    let mid: Vec<i64> = left.iter().zip(right).map(|(l, r)| (l + r) >> 1).collect();
+    let assignments = [
+        ChannelAssignment::Independent(2),
+        ChannelAssignment::LeftSide,
+        ChannelAssignment::RightSide,
+        ChannelAssignment::MidSide,
+    ];
+    let pair = |assignment| match assignment {
+        ChannelAssignment::LeftSide => (0, 3),
+        ChannelAssignment::RightSide => (3, 1),
+        ChannelAssignment::MidSide => (2, 3),
+        _ => (0, 1),
+    };
+    let inputs = [(left, bps), (right, bps), (&mid[..], bps), (&side[..], bps + 1)];
+
+    if params.stereo == StereoMode::Adaptive {
+        let costs = inputs.map(|(samples, _)| fixed_cost(samples));
+        let best = assignments
+            .into_iter()
+            .min_by_key(|a| {
+                let (a, b) = pair(*a);
+                costs[a] + costs[b]
+            })
+            .unwrap();
+        let (a, b) = pair(best);
+        let subframes = [a, b].map(|i| encode_subframe(params, inputs[i].0, inputs[i].1));
+        return (best, subframes.into());
+    }
+    let mut subframes = inputs.map(|(samples, bps)| Some(encode_subframe(params, samples, bps)));
+    let best = assignments
+        .into_iter()
+        .min_by_key(|a| {
+            let (a, b) = pair(*a);
+            subframes[a].as_ref().unwrap().len() + subframes[b].as_ref().unwrap().len()
+        })
+        .unwrap();
+    let (a, b) = pair(best);
+    let pair = vec![subframes[a].take().unwrap(), subframes[b].take().unwrap()];
+    (best, pair)
+}
+
+/// Rough size of a channel, the sum of its second order fixed residual
+fn fixed_cost(samples: &[i64]) -> u64 {
+    samples
+        .windows(3)
+        .map(|w| (w[2] - 2 * w[1] + w[0]).unsigned_abs())
+        .sum()
+}
+
+enum Model {
+    Verbatim,
+    Fixed(usize),
+    Lpc {
+        coefs: Vec<i64>,
+        precision: u32,
+        shift: u32,
+    },
+}
+
+impl Model {
+    fn order(&self) -> usize {
+        match self {
+            Model::Verbatim => 0,
+            Model::Fixed(order) => *order,
+            Model::Lpc { coefs, .. } => coefs.len(),
+        }
+    }
+}
+
+/// Picks the smallest subframe model for `samples` and writes it
+fn encode_subframe(params: &EncodeParams, samples: &[i64], bps: u32) -> BitWriter {
+    let mut w = BitWriter::new();
+    if samples.iter().all(|s| *s == samples[0]) {
+        w.write_bits(0, 8);
+        w.write_signed(samples[0], bps);
+        return w;
+    }
+    let wasted = samples
+        .iter()
+        .fold(0, |acc, s| acc | s)
+        .trailing_zeros()
+        .min(bps - 1);
+    let shifted: Vec<i64>;
+    let samples = match wasted {
+        0 => samples,
+        _ => {
+            shifted = samples.iter().map(|s| s >> wasted).collect();
+            &shifted
+        }
+    };
+    let bps = bps - wasted;
+    let n = samples.len();
+
+    let mut best = (Model::Verbatim, n * bps as usize, None);
+    let consider = |model: Model, best: &mut (Model, usize, Option<Residual>)| {
+        let order = model.order();
+        let Some(residual) = compute_residual(&model, samples) else {
+            return;
+        };
+        let Some(coding) = Residual::plan(params, &residual, n, order) else {
+            return;
+        };
+        let header_bits = match &model {
+            Model::Lpc { coefs, precision, .. } => 4 + 5 + coefs.len() * *precision as usize,
+            _ => 0,
+        };
+        let bits = order * bps as usize + header_bits + coding.bits;
+        if bits < best.1 {
+            *best = (model, bits, Some(coding));
+        }
+    };
+    for order in 0..=MAX_FIXED_ORDER.min(n - 1) {
+        consider(Model::Fixed(order), &mut best);
+    }
+    let max_order = params.max_lpc_order.min(n - 1);
+    if max_order > 0 {
+        for model in lpc_models(params, samples, max_order) {
+            consider(model, &mut best);
+        }
+    }
+
+    let (model, _, residual) = best;
+    let ty = match &model {
+        Model::Verbatim => 0b000001,
+        Model::Fixed(order) => 0b001000 | *order as u64,
+        Model::Lpc { coefs, .. } => 0b100000 | (coefs.len() as u64 - 1),
+    };
+    w.write_bits(ty << 1 | (wasted > 0) as u64, 8);
+    if wasted > 0 {
+        w.write_unary(wasted as u64 - 1);
+    }
+    match &model {
+        Model::Verbatim => samples.iter().for_each(|s| w.write_signed(*s, bps)),
+        model => {
+            let order = model.order();
+            samples[..order].iter().for_each(|s| w.write_signed(*s, bps));
+            if let Model::Lpc {
+                coefs,
+                precision,
+                shift,
+            } = model
+            {
+                w.write_bits(*precision as u64 - 1, 4);
+                w.write_signed(*shift as i64, 5);
+                coefs.iter().for_each(|c| w.write_signed(*c, *precision));
+            }
+            residual.unwrap().write(&mut w);
+        }
+    }
+    w
+}
+
+fn compute_residual(model: &Model, samples: &[i64]) -> Option<Vec<i64>> {
+    let order = model.order();
+    let residual: Vec<i64> = match model {
+        Model::Verbatim => return None,
+        Model::Fixed(order) => (*order..samples.len())
+            .map(|i| {
+                let s = samples;
+                s[i] - match order {
+                    0 => 0,
+                    1 => s[i - 1],
+                    2 => 2 * s[i - 1] - s[i - 2],
+                    3 => 3 * s[i - 1] - 3 * s[i - 2] + s[i - 3],
+                    _ => 4 * s[i - 1] - 6 * s[i - 2] + 4 * s[i - 3] - s[i - 4],
+                }
+            })
+            .collect(),
+        Model::Lpc { coefs, shift, .. } => (order..samples.len())
+            .map(|i| {
+                let prediction: i64 = coefs
+                    .iter()
+                    .zip(samples[i - order..i].iter().rev())
+                    .map(|(c, s)| c * s)
+                    .sum();
+                samples[i] - (prediction >> shift)
+            })
+            .collect(),
+    };
+    // the residual has to fit in 32 bits
+    residual
+        .iter()
+        .all(|r| i32::try_from(*r).is_ok())
+        .then_some(residual)
+}
+
+/// Quantized LPC models worth trying for `samples`
+fn lpc_models(params: &EncodeParams, samples: &[i64], max_order: usize) -> Vec<Model> {
+    let n = samples.len();
+    let windowed: Vec<f64> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, s)| *s as f64 * tukey(i, n, 0.5))
+        .collect();
+    let autocorr: Vec<f64> = (0..=max_order)
+        .map(|lag| {
+            windowed[lag..]
+                .iter()
+                .zip(&windowed)
+                .map(|(a, b)| a * b)
+                .sum()
+        })
+        .collect();
+    if autocorr[0] == 0.0 {
+        return Vec::new();
+    }
+    let (lpcs, errors) = levinson_durbin(&autocorr, max_order);
+
+    let orders: Vec<usize> = match params.exhaustive_model_search {
+        true => (1..=lpcs.len()).collect(),
+        false => {
+            let best = (1..=lpcs.len()).min_by(|a, b| {
+                let bits = |order: usize| {
+                    let per_sample = (0.5 * (errors[order - 1] * 0.5 / n as f64).log2()).max(0.0);
+                    per_sample * (n - order) as f64 + (order * params.qlp_precision as usize) as f64
+                };
+                bits(*a).total_cmp(&bits(*b))
+            });
+            best.into_iter().collect()
+        }
+    };
+    orders
+        .into_iter()
+        .filter_map(|order| quantize(&lpcs[order - 1], params.qlp_precision))
+        .collect()
+}
+
+fn tukey(i: usize, n: usize, p: f64) -> f64 {
+    let taper = (p * (n - 1) as f64 / 2.0).floor() as usize;
+    if taper == 0 {
+        return 1.0;
+    }
+    let x = match i {
+        i if i < taper => i,
+        i if i >= n - taper => n - 1 - i,
+        _ => return 1.0,
+    };
+    0.5 - 0.5 * (std::f64::consts::PI * x as f64 / taper as f64).cos()
+}
+
+/// Returns the predictor coefficients and the prediction error for each order
+fn levinson_durbin(autocorr: &[f64], max_order: usize) -> (Vec<Vec<f64>>, Vec<f64>) {
+    let mut lpc = vec![0.0; max_order];
+    let mut err = autocorr[0];
+    let mut lpcs = Vec::with_capacity(max_order);
+    let mut errors = Vec::with_capacity(max_order);
+    for i in 0..max_order {
+        let mut r = -autocorr[i + 1];
+        for j in 0..i {
+            r -= lpc[j] * autocorr[i - j];
+        }
+        r /= err;
+        lpc[i] = r;
+        for j in 0..i / 2 {
+            let tmp = lpc[j];
+            lpc[j] += r * lpc[i - 1 - j];
+            lpc[i - 1 - j] += r * tmp;
+        }
+        if i % 2 == 1 {
+            lpc[i / 2] += lpc[i / 2] * r;
+        }
+        err *= 1.0 - r * r;
+        // coefficients are negated so that prediction is a plain weighted sum
+        lpcs.push(lpc[..=i].iter().map(|c| -c).collect());
+        errors.push(err);
+        if err <= 0.0 {
+            break;
+        }
+    }
+    (lpcs, errors)
+}
+
+fn quantize(lpc: &[f64], precision: u32) -> Option<Model> {
+    let cmax = lpc.iter().fold(0.0f64, |m, c| m.max(c.abs()));
+    if cmax <= 0.0 || !cmax.is_finite() {
+        return None;
+    }
+    let log2cmax = cmax.log2().floor() as i32 + 1;
+    let qmax = (1i64 << (precision - 1)) - 1;
+    let shift = (precision as i32 - 1 - log2cmax).min(15);
+    if shift < 0 {
+        return None;
+    }
+    let mut error = 0.0;
+    let coefs = lpc
+        .iter()
+        .map(|c| {
+            error += c * (1 << shift) as f64;
+            let q = (error.round() as i64).clamp(-qmax - 1, qmax);
+            error -= q as f64;
+            q
+        })
+        .collect();
+    Some(Model::Lpc {
+        coefs,
+        precision,
+        shift: shift as u32,
+    })
+}
+
+/// Partitioned Rice coding plan for a residual
+struct Residual {
+    residual: Vec<i64>,
+    order: usize,
+    partition_order: u32,
+    params: Vec<u32>,
+    bits: usize,
+}
+
+impl Residual {
+    fn plan(params: &EncodeParams, residual: &[i64], n: usize, order: usize) -> Option<Residual> {
+        let folded: Vec<u64> = residual
+            .iter()
+            .map(|r| ((r << 1) ^ (r >> 63)) as u64)
+            .collect();
+        let mut best: Option<(u32, Vec<u32>, usize)> = None;
+        for partition_order in params.min_partition_order..=params.max_partition_order {
+            let partition_len = n >> partition_order;
+            if partition_len << partition_order != n
+                || (partition_order > 0 && partition_len <= order)
+            {
+                break;
+            }
+            let mut rice_params = Vec::with_capacity(1 << partition_order);
+            let mut bits = 0;
+            let mut start = 0;
+            for p in 0..1usize << partition_order {
+                let len = partition_len - if p == 0 { order } else { 0 };
+                let sum: u64 = folded[start..start + len].iter().sum();
+                start += len;
+                let (param, param_bits) = best_rice_param(sum, len);
+                rice_params.push(param);
+                bits += param_bits;
+            }
+            let param_len = match rice_params.iter().any(|p| *p > 14) {
+                true => 5,
+                false => 4,
+            };
+            bits += 6 + param_len * rice_params.len();
+            if best.as_ref().is_none_or(|b| bits < b.2) {
+                best = Some((partition_order, rice_params, bits));
+            }
+        }
+        let (partition_order, params, bits) = best?;
+        Some(Residual {
+            residual: residual.to_vec(),
+            order,
+            partition_order,
+            params,
+            bits,
+        })
+    }
+
+    fn write(&self, w: &mut BitWriter) {
+        let rice2 = self.params.iter().any(|p| *p > 14);
+        w.write_bits(rice2 as u64, 2);
+        w.write_bits(self.partition_order as u64, 4);
+        let partition_len = (self.residual.len() + self.order) >> self.partition_order;
+        let mut start = 0;
+        for (p, param) in self.params.iter().enumerate() {
+            let len = partition_len - if p == 0 { self.order } else { 0 };
+            w.write_bits(*param as u64, if rice2 { 5 } else { 4 });
+            self.residual[start..start + len]
+                .iter()
+                .for_each(|r| w.write_rice(*r, *param));
+            start += len;
+        }
+    }
+}
+
+/// Rice parameter with the smallest estimated size for `len` values summing to `sum`
+fn best_rice_param(sum: u64, len: usize) -> (u32, usize) {
+    if len == 0 {
+        return (0, 0);
+    }
+    let mean = sum / len as u64;
+    let guess = (64 - mean.leading_zeros()).min(MAX_RICE_PARAM);
+    (guess.saturating_sub(1)..=(guess + 1).min(MAX_RICE_PARAM))
+        .map(|k| (k, len * (k as usize + 1) + (sum >> k) as usize))
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap()
+}
@@ -1,3 +1,9 @@
+use std::io::Read;
+
+use crate::crc::crc8_update;
+use crate::error::Error::*;
+use crate::metadata::StreamInfo;
+use crate::Result;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SampleRate {
@@ -55,7 +61,25 @@ impl SampleRate {
             _ => unreachable!("Invalid sample rate")
         }
     }
-    pub fn to_u8(&self) -> u8 {
+    /// Sample rate in Hz for the codes that don't need the header trailer or STREAMINFO
+    pub fn hz(self) -> Option<u32> {
+        let hz = match self {
+            SampleRate::Hz88_2k => 88200,
+            SampleRate::Hz176_4k => 176400,
+            SampleRate::Hz192k => 192000,
+            SampleRate::Hz8k => 8000,
+            SampleRate::Hz16k => 16000,
+            SampleRate::Hz22_05k => 22050,
+            SampleRate::Hz24k => 24000,
+            SampleRate::Hz32k => 32000,
+            SampleRate::Hz44_1k => 44100,
+            SampleRate::Hz48k => 48000,
+            SampleRate::Hz96k => 96000,
+            _ => return None,
+        };
+        Some(hz)
+    }
+    pub fn to_u8(self) -> u8 {
         match self {
             SampleRate::FromMetaBlock => 0b0000,
             SampleRate::Hz88_2k => 0b0001,
@@ -77,12 +101,227 @@ impl SampleRate {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockingStrategy {
+    /// Every frame but the last has the same block size, the header carries the frame number
+    Fixed,
+    /// The header carries the number of the first sample in the frame
+    Variable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelAssignment {
+    /// Number of channels coded independently
+    Independent(u8),
+    LeftSide,
+    RightSide,
+    MidSide,
+}
 
+impl ChannelAssignment {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        let value = match val {
+            0b0000..=0b0111 => ChannelAssignment::Independent(val + 1),
+            0b1000 => ChannelAssignment::LeftSide,
+            0b1001 => ChannelAssignment::RightSide,
+            0b1010 => ChannelAssignment::MidSide,
+            _ => return None,
+        };
+        Some(value)
+    }
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ChannelAssignment::Independent(n) => n - 1,
+            ChannelAssignment::LeftSide => 0b1000,
+            ChannelAssignment::RightSide => 0b1001,
+            ChannelAssignment::MidSide => 0b1010,
+        }
+    }
+    pub fn channels(self) -> u8 {
+        match self {
+            ChannelAssignment::Independent(n) => n,
+            _ => 2,
+        }
+    }
+}
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FrameHeader {
-    sync_code: [u8; 2],
-    reserved: bool,
-    blocking_strategy: bool,
-    block_size: u8,
+    blocking_strategy: BlockingStrategy,
+    block_size: u32,
     sample_rate: SampleRate,
+    sample_rate_hz: Option<u32>,
+    channel_assignment: ChannelAssignment,
+    bits_per_sample: Option<u8>,
+    number: u64,
+    crc: u8,
+    len: usize,
+}
+
+/// Reads bytes while keeping the CRC-8 of everything read so far
+struct Crc8Reader<'r, R> {
+    inner: &'r mut R,
+    crc: u8,
+    len: usize,
+}
+
+impl<R: Read> Crc8Reader<'_, R> {
+    fn byte(&mut self) -> Result<u8> {
+        let mut byte = [0];
+        self.inner.read_exact(&mut byte)?;
+        self.crc = crc8_update(self.crc, byte[0]);
+        self.len += 1;
+        Ok(byte[0])
+    }
+    fn be_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes([self.byte()?, self.byte()?]))
+    }
+    /// Reads the "UTF-8" coded frame or sample number, up to 36 bits
+    fn coded_number(&mut self) -> Result<u64> {
+        let first = self.byte()?;
+        let extra = match first.leading_ones() {
+            0 => return Ok(first as u64),
+            n @ 2..=7 => n as usize - 1,
+            _ => return Err(InvalidFormat),
+        };
+        let mut value = (first & (0x7F >> extra)) as u64;
+        for _ in 0..extra {
+            let byte = self.byte()?;
+            if byte & 0xC0 != 0x80 {
+                return Err(InvalidFormat);
+            }
+            value = (value << 6) | (byte & 0x3F) as u64;
+        }
+        Ok(value)
+    }
+}
+
+impl FrameHeader {
+    /// Largest possible header, sync code and CRC included
+    pub const MAX_LEN: usize = 16;
+
+    /// Reads a frame header starting at the sync code and verifies its CRC-8
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<FrameHeader> {
+        let mut reader = Crc8Reader {
+            inner: reader,
+            crc: 0,
+            len: 0,
+        };
+        let sync = reader.be_u16()?;
+        if sync & 0xFFFE != 0xFFF8 {
+            return Err(InvalidFormat);
+        }
+        let blocking_strategy = match sync & 1 {
+            0 => BlockingStrategy::Fixed,
+            _ => BlockingStrategy::Variable,
+        };
+        let byte = reader.byte()?;
+        let (block_size_code, sample_rate) = (byte >> 4, SampleRate::from_u8(byte & 0x0F));
+        let byte = reader.byte()?;
+        let channel_assignment = ChannelAssignment::from_u8(byte >> 4)
+            .ok_or(Unsupported("reserved channel assignment"))?;
+        let bits_per_sample = match (byte >> 1) & 0b111 {
+            0b000 => None,
+            0b001 => Some(8),
+            0b010 => Some(12),
+            0b100 => Some(16),
+            0b101 => Some(20),
+            0b110 => Some(24),
+            0b111 => Some(32),
+            _ => return Err(Unsupported("reserved sample size")),
+        };
+        if byte & 1 != 0 {
+            return Err(InvalidFormat);
+        }
+        if sample_rate == SampleRate::Invalid {
+            return Err(Unsupported("reserved sample rate"));
+        }
+        let number = reader.coded_number()?;
+        if blocking_strategy == BlockingStrategy::Fixed && number >= 1 << 31 {
+            return Err(InvalidFormat);
+        }
+        let block_size = match block_size_code {
+            0b0000 => return Err(Unsupported("reserved block size")),
+            0b0001 => 192,
+            0b0010..=0b0101 => 576 << (block_size_code - 2),
+            0b0110 => reader.byte()? as u32 + 1,
+            0b0111 => reader.be_u16()? as u32 + 1,
+            _ => 256 << (block_size_code - 8),
+        };
+        let sample_rate_hz = match sample_rate {
+            SampleRate::KHz8b => Some(reader.byte()? as u32 * 1000),
+            SampleRate::Hz16b => Some(reader.be_u16()? as u32),
+            SampleRate::Hz16bTens => Some(reader.be_u16()? as u32 * 10),
+            rate => rate.hz(),
+        };
+        let crc = reader.crc;
+        if reader.byte()? != crc {
+            return Err(CrcMismatch {
+                frame: number,
+                offset: 0,
+            });
+        }
+        Ok(Self {
+            blocking_strategy,
+            block_size,
+            sample_rate,
+            sample_rate_hz,
+            channel_assignment,
+            bits_per_sample,
+            number,
+            crc,
+            len: reader.len,
+        })
+    }
+    pub fn parse(buf: &[u8]) -> Result<FrameHeader> {
+        Self::read_from(&mut &buf[..])
+    }
+    pub fn blocking_strategy(&self) -> BlockingStrategy {
+        self.blocking_strategy
+    }
+    /// Number of samples per channel in the frame
+    pub fn block_size(&self) -> u32 {
+        self.block_size
+    }
+    pub fn sample_rate_code(&self) -> SampleRate {
+        self.sample_rate
+    }
+    /// Sample rate in Hz, `None` if it has to be taken from STREAMINFO
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate_hz
+    }
+    pub fn channel_assignment(&self) -> ChannelAssignment {
+        self.channel_assignment
+    }
+    pub fn channels(&self) -> u8 {
+        self.channel_assignment.channels()
+    }
+    /// Bits per sample, `None` if it has to be taken from STREAMINFO
+    pub fn bits_per_sample(&self) -> Option<u8> {
+        self.bits_per_sample
+    }
+    /// Frame number for fixed blocking streams, sample number of the first sample otherwise
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+    pub fn crc(&self) -> u8 {
+        self.crc
+    }
+    /// Length of the header in bytes, sync code and CRC included
+    pub fn header_len(&self) -> usize {
+        self.len
+    }
+    /// Number of the first sample in the frame
+    pub fn first_sample(&self, stream_info: &StreamInfo) -> u64 {
+        match self.blocking_strategy {
+            BlockingStrategy::Fixed => self.number * stream_info.max_block_size as u64,
+            BlockingStrategy::Variable => self.number,
+        }
+    }
+    pub fn sample_rate_or(&self, stream_info: &StreamInfo) -> u32 {
+        self.sample_rate_hz.unwrap_or(stream_info.sample_rate)
+    }
+    pub fn bits_per_sample_or(&self, stream_info: &StreamInfo) -> u8 {
+        self.bits_per_sample.unwrap_or(stream_info.bps)
+    }
 }
@@ -1,2 +1,102 @@
+mod bit_reader;
+mod bit_writer;
+mod decode;
+mod encode;
 mod header;
+pub use decode::AudioBlock;
+pub use encode::StereoMode;
+pub use header::*;
+pub(crate) use bit_reader::is_eof;
+pub(crate) use decode::{decode_frame, DecodedFrame};
+pub(crate) use encode::{encode_frame, EncodeParams};
 
+use std::io::Read;
+
+use crate::error::Error::*;
+use crate::id3::prefix_len;
+use crate::{read_with_id3v2, Result};
+
+/// Finds the first valid frame header in `buf`, returning its position.
+///
+/// Candidates whose header runs past the end of `buf` are skipped.
+pub fn find_frame_header(buf: &[u8]) -> Option<(usize, FrameHeader)> {
+    let mut pos = 0;
+    while pos + 1 < buf.len() {
+        let next = buf[pos..].windows(2).position(|w| w[0] == 0xFF && w[1] & 0xFE == 0xF8)?;
+        pos += next;
+        if let Ok(header) = FrameHeader::parse(&buf[pos..]) {
+            return Some((pos, header));
+        }
+        pos += 1;
+    }
+    None
+}
+
+/// Skips the metadata blocks and locates the first frame, returning its offset from the
+/// start of the stream
+pub fn find_first_frame<R: Read>(reader: &mut R) -> Result<(u64, FrameHeader)> {
+    let (id3v2, blocks) = read_with_id3v2(reader)?;
+    let mut offset = prefix_len(id3v2.as_ref()) + 4 + blocks
+        .iter()
+        .map(|b| 4 + b.block_size() as u64)
+        .sum::<u64>();
+    let mut buf = Vec::new();
+    loop {
+        let read = reader.take(4096).read_to_end(&mut buf)?;
+        let eof = read == 0;
+        // a header close to the end may still be incomplete
+        let limit = match eof {
+            true => buf.len(),
+            false => buf.len().saturating_sub(FrameHeader::MAX_LEN),
+        };
+        if let Some((pos, header)) = find_frame_header(&buf).filter(|(pos, _)| *pos < limit) {
+            return Ok((offset + pos as u64, header));
+        }
+        if eof {
+            return Err(InvalidFormat);
+        }
+        offset += limit as u64;
+        buf.drain(..limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crc::crc8_update;
+
+    fn with_crc(mut header: Vec<u8>) -> Vec<u8> {
+        header.push(header.iter().fold(0, |crc, b| crc8_update(crc, *b)));
+        header
+    }
+
+    #[test]
+    fn parse_headers() {
+        let header = FrameHeader::parse(&with_crc(vec![0xFF, 0xF8, 0xC9, 0xA8, 0x00])).unwrap();
+        assert_eq!(header.blocking_strategy(), BlockingStrategy::Fixed);
+        assert_eq!(header.block_size(), 4096);
+        assert_eq!(header.sample_rate(), Some(44100));
+        assert_eq!(header.channel_assignment(), ChannelAssignment::MidSide);
+        assert_eq!(header.bits_per_sample(), Some(16));
+        assert_eq!(header.header_len(), 6);
+
+        // variable blocking, 16 bit block size, sample rate in tens of Hz, sample number 200
+        let bytes = with_crc(vec![0xFF, 0xF9, 0x7E, 0x10, 0xC3, 0x88, 0x01, 0x00, 0x12, 0x34]);
+        let header = FrameHeader::parse(&bytes).unwrap();
+        assert_eq!(header.blocking_strategy(), BlockingStrategy::Variable);
+        assert_eq!(header.number(), 200);
+        assert_eq!(header.block_size(), 257);
+        assert_eq!(header.sample_rate(), Some(0x1234 * 10));
+        assert_eq!(header.channel_assignment(), ChannelAssignment::Independent(2));
+        assert_eq!(header.bits_per_sample(), None);
+
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(FrameHeader::parse(&corrupt).is_err());
+
+        let mut stream = vec![0xFF, 0xF8, 0x00, 0xFF];
+        stream.extend_from_slice(&bytes);
+        let (pos, found) = find_frame_header(&stream).unwrap();
+        assert_eq!((pos, found), (4, header));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::error::Error::*;
+use crate::Result;
+
+/// An ID3v2 tag found in front of the `fLaC` marker.
+///
+/// FLAC has no place for ID3 tags but some taggers prepend one anyway, the readers skip it
+/// and keep it around so it can be inspected or written back unchanged.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Id3v2Tag {
+    /// The whole tag, header and footer included
+    raw: Vec<u8>,
+}
+
+impl std::fmt::Debug for Id3v2Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Id3v2Tag")
+            .field("version", &self.version())
+            .field("flags", &self.flags())
+            .field("len", &format_args!("[{} bytes]", self.raw.len()))
+            .finish()
+    }
+}
+
+impl Id3v2Tag {
+    pub const HEADER_LEN: usize = 10;
+    const FOOTER_FLAG: u8 = 0x10;
+
+    /// Whether `buf` starts like an ID3v2 header
+    pub fn is_header(buf: &[u8]) -> bool {
+        buf.starts_with(b"ID3")
+    }
+    /// Length of the whole tag described by `header`
+    pub fn tag_len(header: &[u8; Self::HEADER_LEN]) -> Result<usize> {
+        let syncsafe = &header[6..];
+        if !Self::is_header(header) || header[3] == 0xFF || syncsafe.iter().any(|b| b & 0x80 != 0) {
+            return Err(InvalidFormat);
+        }
+        let size = syncsafe.iter().fold(0, |size, b| size << 7 | *b as usize);
+        let footer = match header[5] & Self::FOOTER_FLAG {
+            0 => 0,
+            _ => Self::HEADER_LEN,
+        };
+        Ok(Self::HEADER_LEN + size + footer)
+    }
+    /// Wraps a complete tag, as measured by [`tag_len`](Self::tag_len)
+    pub fn from_bytes(raw: Vec<u8>) -> Result<Id3v2Tag> {
+        let header = raw.first_chunk().ok_or(InvalidFormat)?;
+        if Self::tag_len(header)? != raw.len() {
+            return Err(InvalidFormat);
+        }
+        Ok(Self { raw })
+    }
+    /// Major version and revision, `(4, 0)` for ID3v2.4.0
+    pub fn version(&self) -> (u8, u8) {
+        (self.raw[3], self.raw[4])
+    }
+    pub fn flags(&self) -> u8 {
+        self.raw[5]
+    }
+    /// Frames and padding between the header and the footer, still unsynchronised if the
+    /// tag says so
+    pub fn body(&self) -> &[u8] {
+        let end = match self.flags() & Self::FOOTER_FLAG {
+            0 => self.raw.len(),
+            _ => self.raw.len() - Self::HEADER_LEN,
+        };
+        &self.raw[Self::HEADER_LEN..end]
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.raw
+    }
+    /// Length of the whole tag, i.e. the offset of `fLaC` in the stream
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+}
+
+/// Length of the optional ID3v2 tag, the offset of `fLaC` in the stream
+pub(crate) fn prefix_len(tag: Option<&Id3v2Tag>) -> u64 {
+    tag.map_or(0, |tag| tag.len() as u64)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// ID3v2.4 tag with a single TIT2 frame and some padding
+    pub(crate) fn tag(footer: bool) -> Vec<u8> {
+        let mut body = b"TIT2\0\0\0\x06\0\0\x03Title".to_vec();
+        body.resize(body.len() + 100, 0);
+        let size = body.len() as u32;
+        let syncsafe = [21, 14, 7, 0].map(|shift| (size >> shift) as u8 & 0x7F);
+        let mut tag = b"ID3\x04\0".to_vec();
+        tag.push((footer as u8) << 4);
+        tag.extend_from_slice(&syncsafe);
+        tag.extend_from_slice(&body);
+        if footer {
+            tag.extend_from_slice(b"3DI\x04\0\x10");
+            tag.extend_from_slice(&syncsafe);
+        }
+        tag
+    }
+
+    #[test]
+    fn parse_tag() {
+        let bytes = tag(false);
+        let header = bytes.first_chunk().unwrap();
+        assert_eq!(Id3v2Tag::tag_len(header).unwrap(), bytes.len());
+        let id3 = Id3v2Tag::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(id3.version(), (4, 0));
+        assert!(id3.body().starts_with(b"TIT2"));
+        assert_eq!(id3.body().len(), bytes.len() - 10);
+
+        let id3 = Id3v2Tag::from_bytes(tag(true)).unwrap();
+        assert_eq!(id3.body().len(), bytes.len() - 10);
+
+        let mut corrupt = bytes;
+        corrupt[9] |= 0x80;
+        assert!(Id3v2Tag::from_bytes(corrupt).is_err());
+    }
+}
@@ -1,10 +1,29 @@
 mod read;
+mod editor;
+mod decoder;
+mod encoder;
+mod verify;
+mod seek;
+#[cfg(feature = "async")]
 pub mod aysnc_read;
 pub mod frame;
 mod error;
+mod crc;
+mod id3;
+pub mod ogg;
+pub mod replay_gain;
+#[cfg(feature = "encoding")]
+pub mod encoding;
 pub mod metadata;
 pub type Result<T> = std::result::Result<T, error::Error>;
 pub use read::*;
+pub use editor::MetadataEditor;
+pub use decoder::Decoder;
+pub use encoder::{encode, Encoder, EncoderConfig};
+pub use verify::{CrcError, FrameError, Verification};
+pub use error::Error;
+pub use id3::Id3v2Tag;
+pub use rotic_lrc as lrc;
 
 
 
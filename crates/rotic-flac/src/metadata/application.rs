@@ -0,0 +1,87 @@
+use super::ConvertBytes;
+use crate::error::Error;
+
+/// Application IDs registered with the FLAC project
+pub const KNOWN_APPLICATIONS: &[([u8; 4], &str)] = &[
+    (*b"ATCH", "FlacFile"),
+    (*b"BSOL", "beSolo"),
+    (*b"BUGS", "Bugs Player"),
+    (*b"Cues", "GoldWave cue points"),
+    (*b"Fica", "CUE Splitter"),
+    (*b"Ftol", "flac-tools"),
+    (*b"MOTB", "MOTB MetaCzar"),
+    (*b"MPSE", "MP3 Stream Editor"),
+    (*b"MuML", "MusicML: Music Metadata Language"),
+    (*b"RIFF", "Sound Devices RIFF chunk storage"),
+    (*b"SFFL", "Sound Font FLAC"),
+    (*b"SONY", "Sony Creative Software"),
+    (*b"SQEZ", "flacsqueeze"),
+    (*b"TtWv", "TwistedWave"),
+    (*b"UITS", "UITS Embedding tools"),
+    (*b"aiff", "FLAC AIFF chunk storage"),
+    (*b"imag", "flac-image"),
+    (*b"peem", "Parseable Embedded Extensible Metadata"),
+    (*b"qfst", "QFLAC Studio"),
+    (*b"riff", "FLAC RIFF chunk storage"),
+    (*b"tune", "TagTuner"),
+    (*b"w64 ", "FLAC Wave64 chunk storage"),
+    (*b"xbat", "XBAT"),
+    (*b"xmcd", "xmcd"),
+];
+
+/// Name of the application registered under `id`
+pub fn application_name(id: &[u8; 4]) -> Option<&'static str> {
+    KNOWN_APPLICATIONS
+        .iter()
+        .find(|(known, _)| known == id)
+        .map(|(_, name)| *name)
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Application {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl std::fmt::Debug for Application {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Application")
+            .field("id", &String::from_utf8_lossy(&self.id))
+            .field("name", &self.name())
+            .field("data", &format_args!("[{} bytes]", self.data.len()))
+            .finish()
+    }
+}
+
+impl Application {
+    pub fn new(id: [u8; 4], data: Vec<u8>) -> Application {
+        Self { id, data }
+    }
+    pub fn name(&self) -> Option<&'static str> {
+        application_name(&self.id)
+    }
+}
+
+impl ConvertBytes for Application {
+    fn from_bytes(mut buf: Vec<u8>) -> crate::Result<Self> {
+        if buf.len() < 4 {
+            return Err(Error::Truncated {
+                offset: 0,
+                expected: 4,
+                actual: buf.len() as u64,
+            });
+        }
+        let data = buf.split_off(4);
+        Ok(Self {
+            id: [buf[0], buf[1], buf[2], buf[3]],
+            data,
+        })
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + self.data.len());
+        bytes.extend_from_slice(&self.id);
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
@@ -0,0 +1,170 @@
+use super::ConvertBytes;
+use crate::{const_array, error::Error, Stream};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CueSheetIndex {
+    /// Offset in samples, relative to the track offset
+    pub offset: u64,
+    pub number: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CueSheetTrack {
+    /// Offset in samples, relative to the beginning of the FLAC audio stream
+    pub offset: u64,
+    pub number: u8,
+    pub isrc: String,
+    pub is_audio: bool,
+    pub pre_emphasis: bool,
+    pub indices: Vec<CueSheetIndex>,
+}
+
+impl CueSheetTrack {
+    /// Track number of the lead-out track
+    pub const LEAD_OUT_CD: u8 = 170;
+    pub const LEAD_OUT: u8 = 255;
+    pub fn is_lead_out(&self, is_cd: bool) -> bool {
+        match is_cd {
+            true => self.number == Self::LEAD_OUT_CD,
+            false => self.number == Self::LEAD_OUT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CueSheet {
+    pub media_catalog_number: String,
+    /// Number of lead-in samples, only meaningful for CD-DA cue sheets
+    pub lead_in: u64,
+    pub is_cd: bool,
+    pub tracks: Vec<CueSheetTrack>,
+}
+
+impl CueSheet {
+    pub fn lead_out(&self) -> Option<&CueSheetTrack> {
+        self.tracks.last().filter(|t| t.is_lead_out(self.is_cd))
+    }
+    pub fn track(&self, number: u8) -> Option<&CueSheetTrack> {
+        self.tracks.iter().find(|t| t.number == number)
+    }
+}
+
+/// Reads a NUL padded ASCII field
+fn padded_str(buf: &[u8]) -> String {
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).to_string()
+}
+
+fn write_padded(bytes: &mut Vec<u8>, s: &str, len: usize) {
+    let s = &s.as_bytes()[..s.len().min(len)];
+    bytes.extend_from_slice(s);
+    bytes.resize(bytes.len() + len - s.len(), 0);
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(const_array!(bytes, 0, 8))
+}
+
+impl ConvertBytes for CueSheet {
+    fn from_bytes(buf: Vec<u8>) -> crate::Result<Self> {
+        let mut stream = Stream::new(&buf);
+        let media_catalog_number = padded_str(stream.take(128)?);
+        let lead_in = be_u64(stream.take(8)?);
+        let is_cd = stream.take(259)?[0] & 0x80 != 0;
+        let track_count = stream.take(1)?[0];
+        let mut tracks = Vec::with_capacity(track_count as usize);
+        for _ in 0..track_count {
+            let offset = be_u64(stream.take(8)?);
+            let number = stream.take(1)?[0];
+            let isrc = padded_str(stream.take(12)?);
+            let flags = stream.take(14)?[0];
+            let index_count = stream.take(1)?[0];
+            let mut indices = Vec::with_capacity(index_count as usize);
+            for _ in 0..index_count {
+                let index = stream.take(12)?;
+                indices.push(CueSheetIndex {
+                    offset: be_u64(index),
+                    number: index[8],
+                });
+            }
+            tracks.push(CueSheetTrack {
+                offset,
+                number,
+                isrc,
+                is_audio: flags & 0x80 == 0,
+                pre_emphasis: flags & 0x40 != 0,
+                indices,
+            });
+        }
+        if stream.take(1).is_ok() {
+            return Err(Error::InvalidFormat);
+        }
+        Ok(Self {
+            media_catalog_number,
+            lead_in,
+            is_cd,
+            tracks,
+        })
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(396 + self.tracks.len() * 36);
+        write_padded(&mut bytes, &self.media_catalog_number, 128);
+        bytes.extend_from_slice(&self.lead_in.to_be_bytes());
+        bytes.push((self.is_cd as u8) << 7);
+        bytes.resize(bytes.len() + 258, 0);
+        bytes.push(self.tracks.len() as u8);
+        for track in self.tracks {
+            bytes.extend_from_slice(&track.offset.to_be_bytes());
+            bytes.push(track.number);
+            write_padded(&mut bytes, &track.isrc, 12);
+            bytes.push(((!track.is_audio as u8) << 7) | ((track.pre_emphasis as u8) << 6));
+            bytes.resize(bytes.len() + 13, 0);
+            bytes.push(track.indices.len() as u8);
+            for index in track.indices {
+                bytes.extend_from_slice(&index.offset.to_be_bytes());
+                bytes.push(index.number);
+                bytes.extend_from_slice(&[0; 3]);
+            }
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let sheet = CueSheet {
+            media_catalog_number: "1234567890123".to_owned(),
+            lead_in: 88200,
+            is_cd: true,
+            tracks: vec![
+                CueSheetTrack {
+                    offset: 0,
+                    number: 1,
+                    isrc: "USRC17607839".to_owned(),
+                    is_audio: true,
+                    pre_emphasis: false,
+                    indices: vec![
+                        CueSheetIndex { offset: 0, number: 0 },
+                        CueSheetIndex { offset: 588 * 150, number: 1 },
+                    ],
+                },
+                CueSheetTrack {
+                    offset: 588 * 10000,
+                    number: CueSheetTrack::LEAD_OUT_CD,
+                    ..Default::default()
+                },
+            ],
+        };
+        let bytes = sheet.clone().into_bytes();
+        assert_eq!(bytes.len(), 396 + 36 * 2 + 12 * 2);
+        let parsed = CueSheet::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(parsed, sheet);
+        assert_eq!(parsed.lead_out().unwrap().offset, 588 * 10000);
+        assert_eq!(parsed.into_bytes(), bytes);
+    }
+}
@@ -0,0 +1,378 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::VorbisComment;
+use crate::error::Error;
+use crate::lrc::Lrc;
+
+/// Release date of a `DATE` field: a year, a month of a year or a full date
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: u16,
+    pub month: Option<u8>,
+    /// Only set along with `month`
+    pub day: Option<u8>,
+}
+
+impl Date {
+    pub fn year(year: u16) -> Date {
+        Self {
+            year,
+            month: None,
+            day: None,
+        }
+    }
+    pub fn year_month(year: u16, month: u8) -> Option<Date> {
+        (1..=12).contains(&month).then_some(Self {
+            year,
+            month: Some(month),
+            day: None,
+        })
+    }
+    pub fn ymd(year: u16, month: u8, day: u8) -> Option<Date> {
+        let days = match month {
+            2 if year.is_multiple_of(4)
+                && (!year.is_multiple_of(100) || year.is_multiple_of(400)) =>
+            {
+                29
+            }
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        let date = Self::year_month(year, month)?;
+        (1..=days).contains(&day).then_some(Self {
+            day: Some(day),
+            ..date
+        })
+    }
+}
+
+impl FromStr for Date {
+    type Err = Error;
+
+    /// Parses `YYYY`, `YYYY-MM` or `YYYY-MM-DD`, anything after a time separator is ignored
+    fn from_str(s: &str) -> Result<Date, Error> {
+        let s = s.trim();
+        let date = s.split(['T', ' ']).next().unwrap_or(s);
+        let number = |part: Option<&str>, len: usize| {
+            part.filter(|p| p.len() == len && p.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|p| p.parse().ok())
+                .ok_or(Error::InvalidFormat)
+        };
+        let mut parts = date.split('-');
+        let year = number(parts.next(), 4)?;
+        let date = match parts.next() {
+            None => Some(Date::year(year)),
+            month => {
+                let month = number(month, 2)? as u8;
+                match parts.next() {
+                    None => Date::year_month(year, month),
+                    day => Date::ymd(year, month, number(day, 2)? as u8),
+                }
+            }
+        };
+        match parts.next() {
+            None => date.ok_or(Error::InvalidFormat),
+            Some(_) => Err(Error::InvalidFormat),
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}", self.year)?;
+        if let Some(month) = self.month {
+            write!(f, "-{month:02}")?;
+        }
+        if let Some(day) = self.day {
+            write!(f, "-{day:02}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Getter and setter pairs for plain text fields
+macro_rules! text_fields {
+    ($($(#[$doc:meta])* $get:ident, $set:ident => $key:literal;)+) => {
+        impl VorbisComment {
+            $(
+                $(#[$doc])*
+                pub fn $get(&self) -> Option<&str> {
+                    self.get($key)
+                }
+                pub fn $set(&mut self, value: impl Into<String>) {
+                    self.set($key, value);
+                }
+            )+
+        }
+    };
+}
+
+text_fields! {
+    title, set_title => "TITLE";
+    album, set_album => "ALBUM";
+    artist, set_artist => "ARTIST";
+    album_artist, set_album_artist => "ALBUMARTIST";
+    /// First genre, see [`genres`](Self::genres) for all of them
+    genre, set_genre => "GENRE";
+    composer, set_composer => "COMPOSER";
+    /// International Standard Recording Code
+    isrc, set_isrc => "ISRC";
+    lyrics, set_lyrics => "LYRICS";
+    musicbrainz_track_id, set_musicbrainz_track_id => "MUSICBRAINZ_TRACKID";
+    musicbrainz_release_track_id, set_musicbrainz_release_track_id => "MUSICBRAINZ_RELEASETRACKID";
+    musicbrainz_album_id, set_musicbrainz_album_id => "MUSICBRAINZ_ALBUMID";
+    musicbrainz_artist_id, set_musicbrainz_artist_id => "MUSICBRAINZ_ARTISTID";
+    musicbrainz_album_artist_id, set_musicbrainz_album_artist_id => "MUSICBRAINZ_ALBUMARTISTID";
+    musicbrainz_release_group_id, set_musicbrainz_release_group_id => "MUSICBRAINZ_RELEASEGROUPID";
+}
+
+/// Number and total of a track or disc, stored either as `N/M` in the number field or in
+/// a separate total field
+struct Position {
+    number: &'static str,
+    totals: [&'static str; 2],
+}
+
+const TRACK: Position = Position {
+    number: "TRACKNUMBER",
+    totals: ["TRACKTOTAL", "TOTALTRACKS"],
+};
+const DISC: Position = Position {
+    number: "DISCNUMBER",
+    totals: ["DISCTOTAL", "TOTALDISCS"],
+};
+
+fn parse_u32(value: &str) -> Option<u32> {
+    value.trim().parse().ok()
+}
+
+impl VorbisComment {
+    /// All genres, a file may have a `GENRE` field per genre
+    pub fn genres(&self) -> impl Iterator<Item = &str> + '_ {
+        self.get_all("GENRE")
+    }
+    /// `COMMENT`, or `DESCRIPTION` which some taggers use instead
+    pub fn comment(&self) -> Option<&str> {
+        self.get("COMMENT").or_else(|| self.get("DESCRIPTION"))
+    }
+    pub fn set_comment(&mut self, comment: impl Into<String>) {
+        self.set("COMMENT", comment);
+    }
+    pub fn date(&self) -> Option<Date> {
+        self.get("DATE")?.parse().ok()
+    }
+    pub fn set_date(&mut self, date: Date) {
+        self.set("DATE", date.to_string());
+    }
+    /// Year of `DATE`, falling back to its first four digits for dates like `2021/05/04`
+    pub fn year(&self) -> Option<u32> {
+        if let Some(date) = self.date() {
+            return Some(date.year as u32);
+        }
+        let year = self.get("DATE")?.trim().get(..4)?;
+        match year.bytes().all(|b| b.is_ascii_digit()) {
+            true => year.parse().ok(),
+            false => None,
+        }
+    }
+    /// Whether the track is part of a compilation, `COMPILATION=1`
+    pub fn compilation(&self) -> Option<bool> {
+        match self.get("COMPILATION")?.trim() {
+            "1" => Some(true),
+            "0" => Some(false),
+            _ => None,
+        }
+    }
+    pub fn set_compilation(&mut self, compilation: bool) {
+        self.set("COMPILATION", if compilation { "1" } else { "0" });
+    }
+    pub fn bpm(&self) -> Option<f64> {
+        self.get("BPM")?.trim().parse().ok()
+    }
+    pub fn set_bpm(&mut self, bpm: f64) {
+        self.set("BPM", bpm.to_string());
+    }
+    /// Lyrics of `LYRICS`, or `UNSYNCEDLYRICS` which some taggers use instead, synced if
+    /// they are in LRC format
+    pub fn lrc(&self) -> Option<Lrc> {
+        let lyrics = self.get("LYRICS").or_else(|| self.get("UNSYNCEDLYRICS"))?;
+        Some(Lrc::parse(lyrics))
+    }
+    /// Stores the lyrics in LRC format in `LYRICS`
+    pub fn set_lrc(&mut self, lrc: &Lrc) {
+        self.set("LYRICS", lrc.to_string().trim_end());
+    }
+
+    pub fn track_number(&self) -> Option<u32> {
+        self.number(&TRACK)
+    }
+    /// Total from `TRACKTOTAL`, `TOTALTRACKS` or a `TRACKNUMBER` in `N/M` form
+    pub fn track_total(&self) -> Option<u32> {
+        self.total(&TRACK)
+    }
+    /// Sets `TRACKNUMBER`, keeping a total stored in `N/M` form
+    pub fn set_track_number(&mut self, number: u32) {
+        self.set_number(&TRACK, number);
+    }
+    /// Updates the total where it is stored, `TRACKTOTAL` if it isn't yet
+    pub fn set_track_total(&mut self, total: u32) {
+        self.set_total(&TRACK, total);
+    }
+    pub fn disc_number(&self) -> Option<u32> {
+        self.number(&DISC)
+    }
+    /// Total from `DISCTOTAL`, `TOTALDISCS` or a `DISCNUMBER` in `N/M` form
+    pub fn disc_total(&self) -> Option<u32> {
+        self.total(&DISC)
+    }
+    /// Sets `DISCNUMBER`, keeping a total stored in `N/M` form
+    pub fn set_disc_number(&mut self, number: u32) {
+        self.set_number(&DISC, number);
+    }
+    /// Updates the total where it is stored, `DISCTOTAL` if it isn't yet
+    pub fn set_disc_total(&mut self, total: u32) {
+        self.set_total(&DISC, total);
+    }
+
+    /// Number and, in `N/M` form, total of the number field
+    fn split_number(&self, field: &Position) -> Option<(&str, Option<&str>)> {
+        let value = self.get(field.number)?;
+        Some(match value.split_once('/') {
+            Some((number, total)) => (number, Some(total)),
+            None => (value, None),
+        })
+    }
+    fn number(&self, field: &Position) -> Option<u32> {
+        parse_u32(self.split_number(field)?.0)
+    }
+    fn total(&self, field: &Position) -> Option<u32> {
+        field
+            .totals
+            .iter()
+            .find_map(|key| self.get(key).and_then(parse_u32))
+            .or_else(|| parse_u32(self.split_number(field)?.1?))
+    }
+    fn set_number(&mut self, field: &Position, number: u32) {
+        let value = match self.split_number(field) {
+            Some((_, Some(total))) => format!("{number}/{}", total.trim()),
+            _ => number.to_string(),
+        };
+        self.set(field.number, value);
+    }
+    fn set_total(&mut self, field: &Position, total: u32) {
+        if let Some(key) = field.totals.iter().find(|key| self.contains(key)) {
+            return self.set(*key, total.to_string());
+        }
+        match self.split_number(field) {
+            Some((number, Some(_))) => {
+                let value = format!("{}/{total}", number.trim());
+                self.set(field.number, value);
+            }
+            _ => self.set(field.totals[0], total.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!("2021".parse::<Date>().unwrap(), Date::year(2021));
+        assert_eq!(
+            "2021-05".parse::<Date>().unwrap(),
+            Date::year_month(2021, 5).unwrap()
+        );
+        let date: Date = "2021-05-04".parse().unwrap();
+        assert_eq!(date, Date::ymd(2021, 5, 4).unwrap());
+        assert_eq!(date.to_string(), "2021-05-04");
+        assert_eq!("2021-05-04T10:00:00Z".parse::<Date>().unwrap(), date);
+        assert_eq!(Date::ymd(2024, 2, 29).unwrap().to_string(), "2024-02-29");
+        for invalid in [
+            "21",
+            "2021-13",
+            "2023-02-29",
+            "2021-5-4",
+            "2021-05-04-01",
+            "May 2021",
+        ] {
+            assert!(invalid.parse::<Date>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn typed_fields() {
+        let mut comment = VorbisComment::new("rotic");
+        comment.add("TRACKNUMBER", "3/12");
+        comment.add("DISCNUMBER", "1");
+        comment.add("DISCTOTAL", "2");
+        comment.add("DATE", "2021-05-04");
+        comment.add("GENRE", "Jazz");
+        comment.add("GENRE", "Fusion");
+        comment.add("DESCRIPTION", "live");
+
+        assert_eq!(
+            (comment.track_number(), comment.track_total()),
+            (Some(3), Some(12))
+        );
+        assert_eq!(
+            (comment.disc_number(), comment.disc_total()),
+            (Some(1), Some(2))
+        );
+        assert_eq!(comment.year(), Some(2021));
+        assert_eq!(comment.date().unwrap().day, Some(4));
+        assert_eq!(comment.genres().collect::<Vec<_>>(), ["Jazz", "Fusion"]);
+        assert_eq!(comment.comment(), Some("live"));
+
+        comment.set_track_number(4);
+        comment.set_track_total(13);
+        assert_eq!(comment.get("TRACKNUMBER"), Some("4/13"));
+        assert!(!comment.contains("TRACKTOTAL"));
+        comment.set_disc_total(3);
+        assert_eq!(comment.get("DISCTOTAL"), Some("3"));
+
+        let mut comment = VorbisComment::new("rotic");
+        comment.set_track_number(1);
+        comment.set_track_total(9);
+        assert_eq!(comment.get("TRACKNUMBER"), Some("1"));
+        assert_eq!(comment.track_total(), Some(9));
+        comment.set_date(Date::year_month(1999, 12).unwrap());
+        assert_eq!(comment.get("DATE"), Some("1999-12"));
+        for date in ["2021/05/04", "2021.05", "20210504"] {
+            comment.set("DATE", date);
+            assert_eq!((comment.date(), comment.year()), (None, Some(2021)));
+        }
+        comment.set("DATE", "May 2021");
+        assert_eq!(comment.year(), None);
+        comment.set_compilation(true);
+        assert_eq!(comment.compilation(), Some(true));
+        comment.set_bpm(128.0);
+        assert_eq!(comment.get("BPM"), Some("128"));
+        comment.set_musicbrainz_track_id("c4b4c3a2-0000-4000-8000-000000000000");
+        assert!(comment.musicbrainz_track_id().is_some());
+        comment.set_title("Song");
+        assert_eq!(comment.title(), Some("Song"));
+    }
+
+    #[test]
+    fn lyrics() {
+        let mut comment = VorbisComment::new("rotic");
+        assert!(comment.lrc().is_none());
+        comment.add("UNSYNCEDLYRICS", "First line\nSecond line");
+        let lrc = comment.lrc().unwrap();
+        assert!(!lrc.is_synced());
+        assert_eq!(lrc.plain_text(), "First line\nSecond line");
+
+        let mut lrc = Lrc::parse("[ar:Someone]\n[00:01.00]First line\n[00:03.50]Second line");
+        lrc.offset = -200;
+        comment.set_lrc(&lrc);
+        assert_eq!(
+            comment.lyrics(),
+            Some("[ar:Someone]\n[offset:-200]\n[00:01.00]First line\n[00:03.50]Second line")
+        );
+        assert_eq!(comment.lrc().unwrap(), lrc);
+    }
+}
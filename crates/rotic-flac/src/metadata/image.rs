@@ -0,0 +1,251 @@
+/// Properties of an image, read from its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ImageInfo {
+    pub(crate) mime: &'static str,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// Bits per pixel
+    pub(crate) color_depth: u32,
+    /// Palette size of indexed images, 0 otherwise
+    pub(crate) colors: u32,
+}
+
+/// Recognizes JPEG, PNG, GIF, BMP and WebP images
+pub(crate) fn sniff(data: &[u8]) -> Option<ImageInfo> {
+    match data {
+        [0xFF, 0xD8, ..] => jpeg(data),
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => png(data),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => gif(data),
+        [b'B', b'M', ..] => bmp(data),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => webp(data),
+        _ => None,
+    }
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+fn le_u16(data: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+fn le_u24(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 3)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+}
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn jpeg(data: &[u8]) -> Option<ImageInfo> {
+    let mut pos = 2;
+    loop {
+        // markers may be preceded by any number of fill bytes
+        while *data.get(pos)? != 0xFF {
+            pos += 1;
+        }
+        while *data.get(pos)? == 0xFF {
+            pos += 1;
+        }
+        let marker = *data.get(pos)?;
+        pos += 1;
+        match marker {
+            0xD0..=0xD9 | 0x01 => continue,
+            // start of frame, except DHT, JPG and DAC
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                return Some(ImageInfo {
+                    mime: "image/jpeg",
+                    height: be_u16(data, pos + 3)?,
+                    width: be_u16(data, pos + 5)?,
+                    color_depth: *data.get(pos + 2)? as u32 * *data.get(pos + 7)? as u32,
+                    colors: 0,
+                });
+            }
+            _ => pos += be_u16(data, pos)? as usize,
+        }
+    }
+}
+
+fn png(data: &[u8]) -> Option<ImageInfo> {
+    if data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let bit_depth = *data.get(24)? as u32;
+    let (color_depth, colors) = match *data.get(25)? {
+        0 => (bit_depth, 0),
+        2 => (bit_depth * 3, 0),
+        // palette entries are always 8 bit RGB
+        3 => (24, png_palette_len(data)? / 3),
+        4 => (bit_depth * 2, 0),
+        6 => (bit_depth * 4, 0),
+        _ => return None,
+    };
+    Some(ImageInfo {
+        mime: "image/png",
+        width: be_u32(data, 16)?,
+        height: be_u32(data, 20)?,
+        color_depth,
+        colors,
+    })
+}
+
+fn png_palette_len(data: &[u8]) -> Option<u32> {
+    let mut pos = 8;
+    loop {
+        let len = be_u32(data, pos)?;
+        match data.get(pos + 4..pos + 8)? {
+            b"PLTE" => return Some(len),
+            b"IDAT" | b"IEND" => return None,
+            _ => pos += 12 + len as usize,
+        }
+    }
+}
+
+fn gif(data: &[u8]) -> Option<ImageInfo> {
+    let flags = *data.get(10)? as u32;
+    Some(ImageInfo {
+        mime: "image/gif",
+        width: le_u16(data, 6)?,
+        height: le_u16(data, 8)?,
+        color_depth: (((flags >> 4) & 0b111) + 1) * 3,
+        colors: match flags & 0x80 {
+            0 => 0,
+            _ => 1 << ((flags & 0b111) + 1),
+        },
+    })
+}
+
+fn bmp(data: &[u8]) -> Option<ImageInfo> {
+    let (width, height, color_depth) = match le_u32(data, 14)? {
+        12 => (le_u16(data, 18)?, le_u16(data, 20)?, le_u16(data, 24)?),
+        _ => (
+            le_u32(data, 18)?,
+            (le_u32(data, 22)? as i32).unsigned_abs(),
+            le_u16(data, 28)?,
+        ),
+    };
+    let colors = match color_depth {
+        1..=8 => match le_u32(data, 46).unwrap_or(0) {
+            0 => 1 << color_depth,
+            used => used,
+        },
+        _ => 0,
+    };
+    Some(ImageInfo {
+        mime: "image/bmp",
+        width,
+        height,
+        color_depth,
+        colors,
+    })
+}
+
+fn webp(data: &[u8]) -> Option<ImageInfo> {
+    let (width, height, alpha) = match data.get(12..16)? {
+        b"VP8 " => {
+            if data.get(23..26)? != [0x9D, 0x01, 0x2A] {
+                return None;
+            }
+            (
+                le_u16(data, 26)? & 0x3FFF,
+                le_u16(data, 28)? & 0x3FFF,
+                false,
+            )
+        }
+        b"VP8L" => {
+            if *data.get(20)? != 0x2F {
+                return None;
+            }
+            let bits = le_u32(data, 21)?;
+            (
+                (bits & 0x3FFF) + 1,
+                ((bits >> 14) & 0x3FFF) + 1,
+                bits & (1 << 28) != 0,
+            )
+        }
+        b"VP8X" => (
+            le_u24(data, 24)? + 1,
+            le_u24(data, 27)? + 1,
+            *data.get(20)? & 0x10 != 0,
+        ),
+        _ => return None,
+    };
+    Some(ImageInfo {
+        mime: "image/webp",
+        width,
+        height,
+        color_depth: if alpha { 32 } else { 24 },
+        colors: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{Picture, PictureType};
+
+    fn info(mime: &'static str, width: u32, height: u32, depth: u32, colors: u32) -> ImageInfo {
+        ImageInfo {
+            mime,
+            width,
+            height,
+            color_depth: depth,
+            colors,
+        }
+    }
+
+    #[test]
+    fn sniff_headers() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend([0, 0, 1, 0, 0, 0, 0, 200, 4, 3, 0, 0, 0, 0, 0, 0, 0]);
+        png.extend(b"\0\0\0\x0cPLTE");
+        assert_eq!(sniff(&png), Some(info("image/png", 256, 200, 24, 4)));
+        png[25] = 6;
+        png[24] = 8;
+        assert_eq!(sniff(&png), Some(info("image/png", 256, 200, 32, 0)));
+
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0, 4, 0, 0, 0xFF, 0xC2, 0, 17, 8, 1, 44, 1, 144, 3,
+        ];
+        assert_eq!(sniff(&jpeg), Some(info("image/jpeg", 400, 300, 24, 0)));
+
+        let gif = b"GIF89a\x40\x01\xf0\x00\xf7\0\0";
+        assert_eq!(sniff(gif), Some(info("image/gif", 320, 240, 24, 256)));
+
+        let mut bmp = vec![b'B', b'M'];
+        bmp.resize(14, 0);
+        bmp.extend(40u32.to_le_bytes());
+        bmp.extend(640u32.to_le_bytes());
+        bmp.extend((-480i32).to_le_bytes());
+        bmp.extend([1, 0, 8, 0]);
+        bmp.resize(46, 0);
+        bmp.extend(16u32.to_le_bytes());
+        assert_eq!(sniff(&bmp), Some(info("image/bmp", 640, 480, 8, 16)));
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8L\0\0\0\0\x2f".to_vec();
+        webp.extend((99u32 | (49 << 14) | (1 << 28)).to_le_bytes());
+        assert_eq!(sniff(&webp), Some(info("image/webp", 100, 50, 32, 0)));
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X\0\0\0\0\0\0\0\0".to_vec();
+        webp.extend([0x7F, 0x07, 0, 0x37, 0x04, 0]);
+        assert_eq!(sniff(&webp), Some(info("image/webp", 1920, 1080, 24, 0)));
+
+        let mut picture = Picture::from_image_bytes(png.clone()).unwrap();
+        picture.set_picture_type(PictureType::Artist);
+        picture.set_description("band");
+        assert_eq!(
+            (picture.mime_type(), picture.width(), picture.color_depth()),
+            ("image/png", 256, 32)
+        );
+        assert_eq!(
+            (picture.picture_type(), picture.description()),
+            (PictureType::Artist, "band")
+        );
+        assert_eq!(picture.data(), png);
+        assert!(Picture::from_image_bytes(b"text".to_vec()).is_err());
+
+        assert_eq!(sniff(b"not an image"), None);
+        assert_eq!(sniff(&png[..20]), None);
+    }
+}
@@ -2,11 +2,22 @@ mod seek_table;
 mod stream_info;
 mod picture;
 mod vorbis_comment;
-pub use vorbis_comment::VorbisComment;
+mod cue_sheet;
+mod application;
+mod padding;
+mod image;
+mod fields;
+pub use vorbis_comment::{VorbisComment, VorbisCommentRef, METADATA_BLOCK_PICTURE};
 pub use stream_info::StreamInfo;
 pub use picture::*;
-pub use seek_table::SeekTable;
+pub use seek_table::{SeekPoint, SeekTable};
+pub use cue_sheet::{CueSheet, CueSheetIndex, CueSheetTrack};
+pub use application::{application_name, Application, KNOWN_APPLICATIONS};
+pub use padding::Padding;
+pub use fields::Date;
 mod data;
+#[cfg(test)]
+mod tests;
 pub type BlockBytes = Block<Vec<u8>>;
 use std::ops::{Deref, DerefMut};
 
@@ -20,12 +31,21 @@ pub trait BlockType: ConvertBytes {
 impl BlockType for StreamInfo {
     const BLOCK_TYPE: u8 = 0;
 }
+impl BlockType for Padding {
+    const BLOCK_TYPE: u8 = 1;
+}
+impl BlockType for Application {
+    const BLOCK_TYPE: u8 = 2;
+}
 impl BlockType for SeekTable {
     const BLOCK_TYPE: u8 = 3;
 }
 impl BlockType for VorbisComment {
     const BLOCK_TYPE: u8 = 4;
 }
+impl BlockType for CueSheet {
+    const BLOCK_TYPE: u8 = 5;
+}
 impl BlockType for Picture {
     const BLOCK_TYPE: u8 = 6;
 }
@@ -79,17 +99,7 @@ impl<T> Block<T> {
     }
 
     pub fn block_type_str(&self) -> &'static str {
-        match self.block_type {
-            0 => "STREAMINFO",
-            1 => "PADDING",
-            2 => "APPLICATION",
-            3 => "SEEKTABLE",
-            4 => "VORBIS_COMMENT",
-            5 => "CUESHEET",
-            6 => "PICTURE",
-            7..=126 => "reserved",
-            _ => "",
-        }
+        block_type_name(self.block_type)
     }
     pub fn is<B: BlockType>(&self) -> bool {
         self.block_type == B::BLOCK_TYPE
@@ -100,23 +110,102 @@ impl<T> Block<T> {
     pub fn block_size(&self) -> u32 {
         self.block_size
     }
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            last_metadata_block: self.last_metadata_block,
+            block_type: self.block_type,
+            block_size: self.block_size,
+        }
+    }
+    pub fn set_last_metadata_block(&mut self, last: bool) {
+        self.last_metadata_block = last;
+    }
     pub fn into_inner(self) -> T {
         self.block_data
     }
 }
 
+/// Name of a metadata block type as used in the specification
+pub fn block_type_name(block_type: u8) -> &'static str {
+    match block_type {
+        0 => "STREAMINFO",
+        1 => "PADDING",
+        2 => "APPLICATION",
+        3 => "SEEKTABLE",
+        4 => "VORBIS_COMMENT",
+        5 => "CUESHEET",
+        6 => "PICTURE",
+        7..=126 => "reserved",
+        _ => "",
+    }
+}
+
+/// Blocks parsed straight from a borrowed payload, without copying it
+pub trait BorrowBytes<'a>: Sized {
+    const BLOCK_TYPE: u8;
+
+    fn from_slice(buf: &'a [u8]) -> Result<Self>;
+}
+
+/// The four bytes in front of every metadata block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub last_metadata_block: bool,
+    pub block_type: u8,
+    /// Length of the payload
+    pub block_size: u32,
+}
+
+impl BlockHeader {
+    pub const LEN: usize = 4;
+
+    pub fn from_bytes(bytes: [u8; 4]) -> BlockHeader {
+        Self {
+            last_metadata_block: bytes[0] & 0x80 != 0,
+            block_type: bytes[0] & 0x7F,
+            block_size: u32::from_be_bytes([0, bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+    pub fn to_bytes(self) -> [u8; 4] {
+        let size = self.block_size.to_be_bytes();
+        let flag = (self.last_metadata_block as u8) << 7;
+        [flag | self.block_type, size[1], size[2], size[3]]
+    }
+    pub fn is<B: BlockType>(&self) -> bool {
+        self.block_type == B::BLOCK_TYPE
+    }
+    pub fn into_block<T>(self, data: T) -> Block<T> {
+        Block::new(self.last_metadata_block, self.block_type, self.block_size, data)
+    }
+}
+
 pub trait ConvertBytes: Sized {
     fn from_bytes(buf: Vec<u8>) -> Result<Self>;
     fn into_bytes(self) -> Vec<u8>;
 }
 
+impl ConvertBytes for Vec<u8> {
+    fn from_bytes(buf: Vec<u8>) -> Result<Self> {
+        Ok(buf)
+    }
+    fn into_bytes(self) -> Vec<u8> {
+        self
+    }
+}
+
 impl Block<Vec<u8>> {
+    /// Serializes a typed block, the last-block flag is left unset
+    pub fn from_block<B: BlockType>(block: B) -> BlockBytes {
+        let data = block.into_bytes();
+        Block::new(false, B::BLOCK_TYPE, data.len() as u32, data)
+    }
     pub fn convert<T: ConvertBytes>(self) -> Result<Block<T>> {
         Ok(Block {
             last_metadata_block: self.last_metadata_block,
             block_type: self.block_type,
             block_size: self.block_size,
-            block_data: T::from_bytes(self.block_data)?,
+            block_data: T::from_bytes(self.block_data)
+                .map_err(|e| e.in_block(self.block_type, None))?,
         })
     }
 }
@@ -0,0 +1,22 @@
+use super::ConvertBytes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Padding {
+    pub len: usize,
+}
+
+impl Padding {
+    pub fn new(len: usize) -> Padding {
+        Self { len }
+    }
+}
+
+impl ConvertBytes for Padding {
+    fn from_bytes(buf: Vec<u8>) -> crate::Result<Self> {
+        Ok(Self { len: buf.len() })
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        vec![0; self.len]
+    }
+}
@@ -2,7 +2,7 @@ use std::path::Path;
 
 use crate::{error::Error, Stream};
 
-use super::ConvertBytes;
+use super::{image, BorrowBytes, ConvertBytes};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PictureType {
@@ -85,9 +85,37 @@ impl std::fmt::Debug for Picture {
 }
 
 impl Picture {
+    /// Front cover from an encoded JPEG, PNG, GIF, BMP or WebP image, the MIME type,
+    /// size, colour depth and palette size are read from the image header
+    pub fn from_image_bytes(data: Vec<u8>) -> crate::Result<Picture> {
+        let info = image::sniff(&data).ok_or(Error::Unsupported("unrecognized image format"))?;
+        Ok(Self {
+            ty: PictureType::CoverFont,
+            mime: info.mime.to_owned(),
+            description: String::new(),
+            width: info.width,
+            height: info.height,
+            color_depth: info.color_depth,
+            indexed_color_pictures: info.colors,
+            picture: data,
+        })
+    }
+    pub fn from_path<P: AsRef<Path>>(path: P) -> crate::Result<Picture> {
+        Self::from_image_bytes(std::fs::read(path)?)
+    }
     pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
         std::fs::write(path, &self.picture)
     }
+    pub fn set_picture_type(&mut self, ty: PictureType) {
+        self.ty = ty;
+    }
+    pub fn set_description(&mut self, description: impl Into<String>) {
+        self.description = description.into();
+    }
+    /// Encoded image data
+    pub fn data(&self) -> &[u8] {
+        &self.picture
+    }
     pub fn picture_type(&self) -> PictureType {
         self.ty
     }
@@ -110,27 +138,98 @@ impl Picture {
         self.indexed_color_pictures
     }
 }
-fn be_u32(bytes: &[u8]) -> u32 {
-    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+/// A PICTURE block borrowing its strings and image data from the block payload
+#[derive(Clone, PartialEq, Eq)]
+pub struct PictureRef<'a> {
+    ty: PictureType,
+    mime: &'a str,
+    description: &'a str,
+    width: u32,
+    height: u32,
+    color_depth: u32,
+    indexed_color_pictures: u32,
+    picture: &'a [u8],
 }
-impl ConvertBytes for Picture {
-    fn from_bytes(buf: Vec<u8>) -> crate::Result<Self> {
-        let mut stream = Stream::new(&buf);
-        let buf = stream.take(4)?;
-        let Some(ty) = PictureType::from_u8(buf[3]) else {
-            return Err(Error::InvalidFormat);
+
+impl std::fmt::Debug for PictureRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PictureRef")
+            .field("ty", &self.ty)
+            .field("mime", &self.mime)
+            .field("description", &self.description)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("color_depth", &self.color_depth)
+            .field("indexed_color_pictures", &self.indexed_color_pictures)
+            .field("picture", &"[..]")
+            .finish()
+    }
+}
+
+impl<'a> PictureRef<'a> {
+    pub fn picture_type(&self) -> PictureType {
+        self.ty
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn mime_type(&self) -> &'a str {
+        self.mime
+    }
+    pub fn description(&self) -> &'a str {
+        self.description
+    }
+    pub fn color_depth(&self) -> u32 {
+        self.color_depth
+    }
+    pub fn indexed_color_pictures(&self) -> u32 {
+        self.indexed_color_pictures
+    }
+    /// Encoded image data
+    pub fn data(&self) -> &'a [u8] {
+        self.picture
+    }
+    pub fn into_owned(self) -> Picture {
+        Picture {
+            ty: self.ty,
+            mime: self.mime.to_owned(),
+            description: self.description.to_owned(),
+            width: self.width,
+            height: self.height,
+            color_depth: self.color_depth,
+            indexed_color_pictures: self.indexed_color_pictures,
+            picture: self.picture.to_vec(),
+        }
+    }
+}
+
+impl<'a> BorrowBytes<'a> for PictureRef<'a> {
+    const BLOCK_TYPE: u8 = 6;
+
+    fn from_slice(buf: &'a [u8]) -> crate::Result<Self> {
+        let mut stream = Stream::new(buf);
+        let ty = be_u32(stream.take(4)?);
+        let Some(ty) = u8::try_from(ty).ok().and_then(PictureType::from_u8) else {
+            return Err(Error::UnknownPictureType(ty));
         };
         let mime_len = be_u32(stream.take(4)?) as usize;
-        let mime = String::from_utf8_lossy(stream.take(mime_len)?).to_string();
+        let mime = std::str::from_utf8(stream.take(mime_len)?)
+            .map_err(|_| Error::InvalidUtf8 { field: "MIME type" })?;
         let description_len = be_u32(stream.take(4)?) as usize;
-        let description = String::from_utf8_lossy(stream.take(description_len)?).to_string();
+        let description =
+            std::str::from_utf8(stream.take(description_len)?).map_err(|_| Error::InvalidUtf8 {
+                field: "description",
+            })?;
 
         let width = be_u32(stream.take(4)?);
         let height = be_u32(stream.take(4)?);
         let color_depth = be_u32(stream.take(4)?);
         let indexed_color_pictures = be_u32(stream.take(4)?);
         let picture_len = be_u32(stream.take(4)?) as usize;
-        let picture = stream.take(picture_len)?.to_vec();
+        let picture = stream.take(picture_len)?;
         Ok(Self {
             ty,
             mime,
@@ -142,18 +241,27 @@ impl ConvertBytes for Picture {
             picture,
         })
     }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+impl ConvertBytes for Picture {
+    fn from_bytes(buf: Vec<u8>) -> crate::Result<Self> {
+        Ok(PictureRef::from_slice(&buf)?.into_owned())
+    }
 
     fn into_bytes(mut self) -> Vec<u8> {
         let mut bytes = vec![0, 0, 0, self.ty as _];
-        bytes.extend_from_slice(&self.mime.len().to_be_bytes());
+        bytes.extend_from_slice(&(self.mime.len() as u32).to_be_bytes());
         bytes.append(&mut self.mime.into_bytes());
-        bytes.extend_from_slice(&self.description.len().to_be_bytes());
+        bytes.extend_from_slice(&(self.description.len() as u32).to_be_bytes());
         bytes.append(&mut self.description.into_bytes());
         bytes.extend_from_slice(&self.width.to_be_bytes());
         bytes.extend_from_slice(&self.height.to_be_bytes());
         bytes.extend_from_slice(&self.color_depth.to_be_bytes());
         bytes.extend_from_slice(&self.indexed_color_pictures.to_be_bytes());
-        bytes.extend_from_slice(&self.picture.len().to_be_bytes());
+        bytes.extend_from_slice(&(self.picture.len() as u32).to_be_bytes());
         bytes.append(&mut self.picture);
         bytes
     }
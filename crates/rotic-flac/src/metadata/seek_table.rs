@@ -1,16 +1,126 @@
 use super::ConvertBytes;
+use crate::const_array;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SeekPoint {
+    /// Sample number of the first sample in the target frame
+    pub sample_number: u64,
+    /// Offset in bytes from the first byte of the first frame header to the target frame header
+    pub stream_offset: u64,
+    /// Number of samples in the target frame
+    pub frame_samples: u16,
+}
+
+impl SeekPoint {
+    pub const PLACEHOLDER: u64 = u64::MAX;
+    pub const SIZE: usize = 18;
+    pub fn new(sample_number: u64, stream_offset: u64, frame_samples: u16) -> SeekPoint {
+        Self {
+            sample_number,
+            stream_offset,
+            frame_samples,
+        }
+    }
+    pub fn placeholder() -> SeekPoint {
+        Self::new(Self::PLACEHOLDER, 0, 0)
+    }
+    pub fn is_placeholder(&self) -> bool {
+        self.sample_number == Self::PLACEHOLDER
+    }
+}
 
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct SeekTable {
-    
+    points: Vec<SeekPoint>,
+}
+
+impl SeekTable {
+    pub fn new() -> SeekTable {
+        Self::default()
+    }
+    /// All seek points, including placeholders, in stored order
+    pub fn points(&self) -> &[SeekPoint] {
+        &self.points
+    }
+    pub fn points_mut(&mut self) -> &mut Vec<SeekPoint> {
+        &mut self.points
+    }
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+    pub fn push(&mut self, point: SeekPoint) {
+        self.points.push(point);
+    }
+    /// Appends `count` placeholder points which can be filled in later without resizing the block
+    pub fn push_placeholders(&mut self, count: usize) {
+        self.points
+            .extend(std::iter::repeat_n(SeekPoint::placeholder(), count));
+    }
+    pub fn placeholders(&self) -> usize {
+        self.points.iter().filter(|p| p.is_placeholder()).count()
+    }
+    /// Seek points that refer to a real frame
+    pub fn seek_points(&self) -> impl Iterator<Item = &SeekPoint> {
+        self.points.iter().filter(|p| !p.is_placeholder())
+    }
+    /// The point with the greatest sample number not after `sample`
+    pub fn nearest(&self, sample: u64) -> Option<&SeekPoint> {
+        self.seek_points()
+            .filter(|p| p.sample_number <= sample)
+            .max_by_key(|p| p.sample_number)
+    }
 }
 
 impl ConvertBytes for SeekTable {
     fn from_bytes(buf: Vec<u8>) -> crate::Result<Self> {
-        todo!()
+        if !buf.len().is_multiple_of(SeekPoint::SIZE) {
+            return Err(crate::error::Error::InvalidFormat);
+        }
+        let points = buf
+            .chunks_exact(SeekPoint::SIZE)
+            .map(|chunk| SeekPoint {
+                sample_number: u64::from_be_bytes(const_array!(chunk, 0, 8)),
+                stream_offset: u64::from_be_bytes(const_array!(chunk, 8, 8)),
+                frame_samples: u16::from_be_bytes(const_array!(chunk, 16, 2)),
+            })
+            .collect();
+        Ok(Self { points })
     }
 
     fn into_bytes(self) -> Vec<u8> {
-        todo!()
+        let mut bytes = Vec::with_capacity(self.points.len() * SeekPoint::SIZE);
+        for point in self.points {
+            bytes.extend_from_slice(&point.sample_number.to_be_bytes());
+            bytes.extend_from_slice(&point.stream_offset.to_be_bytes());
+            bytes.extend_from_slice(&point.frame_samples.to_be_bytes());
+        }
+        bytes
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_and_nearest() {
+        let mut table = SeekTable::new();
+        table.push(SeekPoint::new(0, 0, 4096));
+        table.push(SeekPoint::new(44100, 12000, 4096));
+        table.push(SeekPoint::new(88200, 25000, 4096));
+        table.push_placeholders(2);
+        let bytes = table.clone().into_bytes();
+        assert_eq!(bytes.len(), 5 * SeekPoint::SIZE);
+        let parsed = SeekTable::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(parsed, table);
+        assert_eq!(parsed.into_bytes(), bytes);
+
+        assert_eq!(table.placeholders(), 2);
+        assert_eq!(table.nearest(50000).unwrap().sample_number, 44100);
+        assert_eq!(table.nearest(88200).unwrap().stream_offset, 25000);
+        assert!(SeekTable::from_bytes(vec![0; 17]).is_err());
+    }
+}
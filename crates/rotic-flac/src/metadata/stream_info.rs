@@ -1,5 +1,8 @@
+use std::time::Duration;
+
 use super::ConvertBytes;
 use crate::const_array;
+use crate::error::Error::InvalidStreamInfo;
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Default)]
 pub struct StreamInfo {
@@ -13,6 +16,76 @@ pub struct StreamInfo {
     pub total_samples: u64,
     pub md5: [u8; 16],
 }
+impl StreamInfo {
+    /// Checks the fields against the constraints of the specification
+    pub fn validate(&self) -> crate::Result<()> {
+        let checks = [
+            (self.min_block_size >= 16, "minimum block size below 16"),
+            (self.max_block_size >= 16, "maximum block size below 16"),
+            (
+                self.min_block_size <= self.max_block_size,
+                "minimum block size above the maximum",
+            ),
+            (
+                self.min_frame_size < 1 << 24 && self.max_frame_size < 1 << 24,
+                "frame size doesn't fit in 24 bits",
+            ),
+            (
+                self.min_frame_size == 0
+                    || self.max_frame_size == 0
+                    || self.min_frame_size <= self.max_frame_size,
+                "minimum frame size above the maximum",
+            ),
+            (self.sample_rate != 0, "sample rate is 0"),
+            (
+                self.sample_rate < 1 << 20,
+                "sample rate doesn't fit in 20 bits",
+            ),
+            ((1..=8).contains(&self.channels), "channels not in 1..=8"),
+            (
+                (4..=32).contains(&self.bps),
+                "bits per sample not in 4..=32",
+            ),
+            (
+                self.total_samples < 1 << 36,
+                "total samples don't fit in 36 bits",
+            ),
+        ];
+        match checks.into_iter().find(|(valid, _)| !valid) {
+            Some((_, what)) => Err(InvalidStreamInfo(what)),
+            None => Ok(()),
+        }
+    }
+    /// The encoder didn't know the length of the stream, `total_samples` is 0
+    pub fn is_total_samples_unknown(&self) -> bool {
+        self.total_samples == 0
+    }
+    /// Whether every block but the last has the same size, frames are then numbered by
+    /// frame rather than by sample
+    pub fn is_fixed_blocksize(&self) -> bool {
+        self.min_block_size == self.max_block_size
+    }
+    /// `None` if the total samples are unknown or the sample rate is 0
+    pub fn duration(&self) -> Option<Duration> {
+        if self.is_total_samples_unknown() || self.sample_rate == 0 {
+            return None;
+        }
+        let rate = self.sample_rate as u64;
+        let nanos = (self.total_samples % rate) * 1_000_000_000 / rate;
+        Some(Duration::new(self.total_samples / rate, nanos as u32))
+    }
+    /// Average bitrate in bits per second of a stream of `size` bytes, e.g. the file size or
+    /// only the audio frames for the audio bitrate. Saturates at `u32::MAX` for a size that
+    /// doesn't fit the duration
+    pub fn average_bitrate(&self, size: u64) -> Option<u32> {
+        if self.is_total_samples_unknown() || self.sample_rate == 0 {
+            return None;
+        }
+        let bits = size as u128 * 8 * self.sample_rate as u128;
+        Some(u32::try_from(bits / self.total_samples as u128).unwrap_or(u32::MAX))
+    }
+}
+
 macro_rules! compute {
     ($v: expr, $as:ident, $offset:expr) => {
         (($v as $as) << $offset)
@@ -21,7 +94,11 @@ macro_rules! compute {
 impl ConvertBytes for StreamInfo {
     fn from_bytes(buf: Vec<u8>) -> crate::Result<Self> {
         if buf.len() != 34 {
-            return Err(crate::error::Error::InvalidFormat);
+            return Err(crate::error::Error::Truncated {
+                offset: 0,
+                expected: 34,
+                actual: buf.len() as u64,
+            });
         }
         let min_block_size = u16::from_be_bytes(const_array!(buf, 0, 2));
         let max_block_size = u16::from_be_bytes(const_array!(buf, 2, 2));
@@ -54,18 +131,114 @@ impl ConvertBytes for StreamInfo {
     fn into_bytes(self) -> Vec<u8> {
         let mut bytes = vec![0; 34];
         bytes[0..2].copy_from_slice(&self.min_block_size.to_be_bytes());
-        bytes[2..4].copy_from_slice(&self.min_block_size.to_be_bytes());
+        bytes[2..4].copy_from_slice(&self.max_block_size.to_be_bytes());
         bytes[4..7].copy_from_slice(&self.min_frame_size.to_be_bytes()[1..]);
         bytes[7..10].copy_from_slice(&self.max_frame_size.to_be_bytes()[1..]);
         bytes[10] = (self.sample_rate >> 12) as u8;
         bytes[11] = ((self.sample_rate >> 4) & 0xff) as u8;
         bytes[12] = ((self.sample_rate << 4) & 0xff) as u8;
-        bytes[12] |= ((self.channels - 1) << 1) & 0xff;
-        bytes[12] |= ((self.bps - 1) >> 4) & 0xff;
-        bytes[13] = ((self.bps - 1) << 4) & 0xff;
-        bytes[13] |= (self.total_samples >> 32) as u8;
+        // channels and bits per sample are stored minus one in 3 and 5 bits
+        let channels = self.channels.saturating_sub(1) & 0b111;
+        let bps = self.bps.saturating_sub(1) & 0b1_1111;
+        bytes[12] |= channels << 1;
+        bytes[12] |= bps >> 4;
+        bytes[13] = bps << 4;
+        bytes[13] |= (self.total_samples >> 32) as u8 & 0x0F;
         bytes[14..18].copy_from_slice(&self.total_samples.to_be_bytes()[4..]);
         bytes[18..34].copy_from_slice(&self.md5);
         bytes
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn derived_properties() {
+        let mut info = StreamInfo {
+            min_block_size: 4096,
+            max_block_size: 4096,
+            sample_rate: 44100,
+            channels: 2,
+            bps: 16,
+            total_samples: 44100 * 90 + 22050,
+            ..Default::default()
+        };
+        info.validate().unwrap();
+        assert!(info.is_fixed_blocksize());
+        assert_eq!(info.duration(), Some(Duration::from_millis(90_500)));
+        // CD quality stored uncompressed
+        let size = info.total_samples * 4;
+        assert_eq!(info.average_bitrate(size), Some(1_411_200));
+        // a tiny sample count in a broken header, not wrapped around
+        let short = StreamInfo {
+            total_samples: 1,
+            ..info.clone()
+        };
+        assert_eq!(short.average_bitrate(size), Some(u32::MAX));
+
+        info.total_samples = 0;
+        assert!(info.is_total_samples_unknown());
+        assert_eq!((info.duration(), info.average_bitrate(size)), (None, None));
+
+        info.min_block_size = 1152;
+        info.validate().unwrap();
+        assert!(!info.is_fixed_blocksize());
+        for (invalid, what) in [
+            (
+                StreamInfo {
+                    min_block_size: 8,
+                    ..info.clone()
+                },
+                "minimum block size below 16",
+            ),
+            (
+                StreamInfo {
+                    max_block_size: 1024,
+                    ..info.clone()
+                },
+                "minimum block size above the maximum",
+            ),
+            (
+                StreamInfo {
+                    sample_rate: 0,
+                    ..info.clone()
+                },
+                "sample rate is 0",
+            ),
+            (
+                StreamInfo {
+                    bps: 3,
+                    ..info.clone()
+                },
+                "bits per sample not in 4..=32",
+            ),
+            (
+                StreamInfo {
+                    bps: 33,
+                    ..info.clone()
+                },
+                "bits per sample not in 4..=32",
+            ),
+            (
+                StreamInfo {
+                    channels: 0,
+                    ..info.clone()
+                },
+                "channels not in 1..=8",
+            ),
+            (
+                StreamInfo {
+                    min_frame_size: 20,
+                    max_frame_size: 10,
+                    ..info.clone()
+                },
+                "minimum frame size above the maximum",
+            ),
+        ] {
+            assert!(matches!(invalid.validate(), Err(Error::InvalidStreamInfo(w)) if w == what));
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Round trip properties for every metadata block: parsing a valid payload and
+//! serializing it again must give back the exact same bytes.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use super::*;
+use crate::error::Error;
+
+/// Parses and serializes `bytes` twice, both passes must agree
+fn round_trip<B: ConvertBytes>(bytes: Vec<u8>) -> Vec<u8> {
+    let first = B::from_bytes(bytes).unwrap().into_bytes();
+    let second = B::from_bytes(first.clone()).unwrap().into_bytes();
+    assert_eq!(first, second);
+    first
+}
+
+fn length_prefixed(bytes: &mut Vec<u8>, data: &[u8], le: bool) {
+    let len = data.len() as u32;
+    match le {
+        true => bytes.extend_from_slice(&len.to_le_bytes()),
+        false => bytes.extend_from_slice(&len.to_be_bytes()),
+    }
+    bytes.extend_from_slice(data);
+}
+
+fn stream_info() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 34)
+}
+
+fn seek_table() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<[u8; SeekPoint::SIZE]>(), 0..16).prop_map(|points| points.concat())
+}
+
+/// Text that is valid UTF-8 half of the time
+fn text() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![".{0,32}".prop_map(String::into_bytes), vec(any::<u8>(), 0..32)]
+}
+
+fn picture() -> impl Strategy<Value = Vec<u8>> {
+    (0..=20u32, "[ -~]{0,32}", text(), any::<[u32; 4]>(), vec(any::<u8>(), 0..256)).prop_map(
+        |(ty, mime, description, dimensions, data)| {
+            let mut bytes = ty.to_be_bytes().to_vec();
+            length_prefixed(&mut bytes, mime.as_bytes(), false);
+            length_prefixed(&mut bytes, &description, false);
+            dimensions
+                .iter()
+                .for_each(|n| bytes.extend_from_slice(&n.to_be_bytes()));
+            length_prefixed(&mut bytes, &data, false);
+            bytes
+        },
+    )
+}
+
+/// Entries are `key=value` most of the time, the key can contain anything but `=`
+fn vorbis_comment() -> impl Strategy<Value = Vec<u8>> {
+    let key = vec(any::<u8>().prop_filter("separator", |b| *b != b'='), 0..16);
+    let entry = prop_oneof![
+        3 => (key, text()).prop_map(|(key, value)| [&key[..], b"=", &value].concat()),
+        1 => vec(any::<u8>(), 0..32),
+    ];
+    (text(), vec(entry, 0..8)).prop_map(|(vendor, entries)| {
+        let mut bytes = Vec::new();
+        length_prefixed(&mut bytes, &vendor, true);
+        bytes.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for entry in entries {
+            length_prefixed(&mut bytes, &entry, true);
+        }
+        bytes
+    })
+}
+
+fn cue_sheet() -> impl Strategy<Value = CueSheet> {
+    let index = (any::<u64>(), any::<u8>()).prop_map(|(offset, number)| CueSheetIndex {
+        offset,
+        number,
+    });
+    let track = (
+        any::<u64>(),
+        any::<u8>(),
+        "[0-9A-Z]{0,12}",
+        any::<bool>(),
+        any::<bool>(),
+        vec(index, 0..4),
+    )
+        .prop_map(|(offset, number, isrc, is_audio, pre_emphasis, indices)| CueSheetTrack {
+            offset,
+            number,
+            isrc,
+            is_audio,
+            pre_emphasis,
+            indices,
+        });
+    ("[ -~]{0,128}", any::<u64>(), any::<bool>(), vec(track, 0..4)).prop_map(
+        |(media_catalog_number, lead_in, is_cd, tracks)| CueSheet {
+            media_catalog_number,
+            lead_in,
+            is_cd,
+            tracks,
+        },
+    )
+}
+
+proptest! {
+    #[test]
+    fn stream_info_round_trip(bytes in stream_info()) {
+        prop_assert_eq!(round_trip::<StreamInfo>(bytes.clone()), bytes);
+    }
+
+    #[test]
+    fn stream_info_fields(
+        block_sizes in any::<(u16, u16)>(),
+        frame_sizes in (0..1u32 << 24, 0..1u32 << 24),
+        sample_rate in 0..1u32 << 20,
+        channels in 1..=8u8,
+        bps in 1..=32u8,
+        total_samples in 0..1u64 << 36,
+        md5 in any::<[u8; 16]>(),
+    ) {
+        let info = StreamInfo {
+            min_block_size: block_sizes.0,
+            max_block_size: block_sizes.1,
+            min_frame_size: frame_sizes.0,
+            max_frame_size: frame_sizes.1,
+            sample_rate,
+            channels,
+            bps,
+            total_samples,
+            md5,
+        };
+        prop_assert_eq!(StreamInfo::from_bytes(info.clone().into_bytes()).unwrap(), info);
+    }
+
+    #[test]
+    fn padding_round_trip(len in 0..1024usize) {
+        prop_assert_eq!(round_trip::<Padding>(vec![0; len]), vec![0; len]);
+    }
+
+    #[test]
+    fn application_round_trip(bytes in vec(any::<u8>(), 4..256)) {
+        prop_assert_eq!(round_trip::<Application>(bytes.clone()), bytes);
+    }
+
+    #[test]
+    fn seek_table_round_trip(bytes in seek_table()) {
+        prop_assert_eq!(round_trip::<SeekTable>(bytes.clone()), bytes);
+    }
+
+    #[test]
+    fn vorbis_comment_round_trip(bytes in vorbis_comment()) {
+        prop_assert_eq!(round_trip::<VorbisComment>(bytes.clone()), bytes);
+    }
+
+    #[test]
+    fn vorbis_comment_edited(bytes in vorbis_comment()) {
+        let mut comment = VorbisComment::from_bytes(bytes).unwrap();
+        comment.add("TITLE", "edited");
+        let parsed = VorbisComment::from_bytes(comment.clone().into_bytes()).unwrap();
+        prop_assert_eq!(parsed.vendor(), comment.vendor());
+        prop_assert_eq!(parsed.comments(), comment.comments());
+    }
+
+    #[test]
+    fn cue_sheet_round_trip(sheet in cue_sheet()) {
+        let bytes = sheet.clone().into_bytes();
+        prop_assert_eq!(CueSheet::from_bytes(bytes.clone()).unwrap(), sheet);
+        prop_assert_eq!(round_trip::<CueSheet>(bytes.clone()), bytes);
+    }
+
+    #[test]
+    fn picture_round_trip(bytes in picture()) {
+        let at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap()) as usize;
+        let start = 8 + at(4);
+        match std::str::from_utf8(&bytes[start + 4..start + 4 + at(start)]) {
+            Ok(_) => prop_assert_eq!(round_trip::<Picture>(bytes.clone()), bytes),
+            Err(_) => {
+                let error = Picture::from_bytes(bytes).unwrap_err();
+                let field = matches!(error, Error::InvalidUtf8 { field: "description" });
+                prop_assert!(field);
+            }
+        }
+    }
+}
@@ -1,110 +1,397 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
 
-static GARBLED: char = '�';
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 
-fn remove_non_printing_char(pat: &str) -> String {
-    pat.chars()
-        .filter(|ch| !format!("{:?}", ch).starts_with("'\\u"))
-        .collect()
-}
+use super::{BlockType, BorrowBytes, ConvertBytes, Picture};
+use crate::error::Error;
+use crate::Stream;
+
+/// Field Ogg Vorbis and Opus store cover art in, a base64 encoded PICTURE block
+pub const METADATA_BLOCK_PICTURE: &str = "METADATA_BLOCK_PICTURE";
 
-use super::ConvertBytes;
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct VorbisComment {
-    refer: String,
-    inner: HashMap<String, String>,
+    vendor: String,
+    comments: Vec<(String, String)>,
     raw: Vec<u8>,
 }
 
 impl std::fmt::Debug for VorbisComment {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("VorbisComment")
-            .field("refer", &self.refer)
-            .field("inner", &self.inner)
+            .field("vendor", &self.vendor)
+            .field("comments", &self.comments)
             .finish()
     }
 }
 
 impl VorbisComment {
-    
-    pub fn refer(&self) -> &str {
-        &self.refer
+    pub fn new(vendor: impl Into<String>) -> VorbisComment {
+        Self {
+            vendor: vendor.into(),
+            ..Default::default()
+        }
+    }
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+    pub fn set_vendor(&mut self, vendor: impl Into<String>) {
+        self.vendor = vendor.into();
     }
     /// Unprocessed Vorbis Comment
     pub fn raw_vorbis_comment(&self) -> &[u8] {
         &self.raw
     }
-    pub fn vorbis_comment(&self) -> &HashMap<String, String> {
-        &self.inner
+    /// All fields in their original order
+    pub fn comments(&self) -> &[(String, String)] {
+        &self.comments
     }
-    pub fn title(&self) -> Option<&str> {
-        self.inner.get("TITLE").map(|s| s.as_str())
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.comments.iter().map(|(k, v)| (k.as_str(), v.as_str()))
     }
-    pub fn year(&self) -> Option<u32> {
-        self.inner.get("DATE")?.parse().ok()
+    pub fn len(&self) -> usize {
+        self.comments.len()
     }
-    pub fn album(&self) -> Option<&str> {
-        self.inner.get("ALBUM").map(|s| s.as_str())
+    pub fn is_empty(&self) -> bool {
+        self.comments.is_empty()
     }
-    pub fn artist(&self) -> Option<&str> {
-        self.inner.get("ARTIST").map(|s| s.as_str())
+    /// First value of the field, field names are case-insensitive
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.comments
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
     }
-    pub fn album_artist(&self) -> Option<&str> {
-        self.inner.get("ALBUMARTIST").map(|s| s.as_str())
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.comments
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
     }
-    pub fn lyrics(&self) -> Option<&str> {
-        self.inner.get("LYRICS").map(|s| s.as_str())
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
     }
-}
-
-impl ConvertBytes for VorbisComment {
-    fn from_bytes(buf: Vec<u8>) -> crate::Result<Self> {
-        let vorbis = String::from_utf8_lossy(&buf).replace(GARBLED, " ");
-        let mut refer = String::new();
-        let mut map: HashMap<String, String> = HashMap::new();
-        for comment in vorbis.split('\0') {
-            let trim = remove_non_printing_char(comment.trim());
-            if trim.is_empty() {
-                continue;
-            }
-            if trim.starts_with("reference") {
-                refer = trim
-            } else {
-                let key_value: Vec<_> = trim.splitn(2, '=').collect();
-                if key_value.len() != 2 {
-                    continue;
-                }
-                let value = match key_value[0] {
-                    "DATE" => key_value[1]
-                        .chars()
-                        .filter(|ch| ch.is_ascii_digit())
-                        .collect(),
-                    _ => key_value[1].trim().replace('"', ""),
-                };
-                map.insert(key_value[0].to_owned(), value);
+    /// Whether `key` can be written as a field name: ASCII 0x20 to 0x7D without `=`
+    pub fn is_valid_key(key: &str) -> bool {
+        key.bytes().all(|b| (0x20..=0x7D).contains(&b) && b != b'=')
+    }
+    /// Appends a value, keeping any existing values of the field
+    ///
+    /// # Panics
+    ///
+    /// If `key` isn't a valid field name, see [`is_valid_key`](Self::is_valid_key)
+    pub fn add(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let key = checked_key(key.into());
+        self.comments.push((key, value.into()));
+    }
+    /// Replaces all values of the field with `value`, keeping the position of the first one
+    ///
+    /// # Panics
+    ///
+    /// If `key` isn't a valid field name, see [`is_valid_key`](Self::is_valid_key)
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let key = checked_key(key.into());
+        let value = value.into();
+        match self.comments.iter().position(|(k, _)| k.eq_ignore_ascii_case(&key)) {
+            Some(index) => {
+                self.comments[index].1 = value;
+                let mut i = 0;
+                self.comments.retain(|(k, _)| {
+                    i += 1;
+                    i - 1 == index || !k.eq_ignore_ascii_case(&key)
+                });
             }
+            None => self.comments.push((key, value)),
         }
+    }
+    /// Removes every value of the field, returning the removed values
+    pub fn remove(&mut self, key: &str) -> Vec<String> {
+        let mut removed = Vec::new();
+        self.comments.retain_mut(|(k, v)| {
+            if k.eq_ignore_ascii_case(key) {
+                removed.push(std::mem::take(v));
+                return false;
+            }
+            true
+        });
+        removed
+    }
+    pub fn retain<F: FnMut(&str, &str) -> bool>(&mut self, mut f: F) {
+        self.comments.retain(|(k, v)| f(k, v));
+    }
+    /// Pictures embedded as `METADATA_BLOCK_PICTURE` fields
+    pub fn pictures(&self) -> impl Iterator<Item = crate::Result<Picture>> + '_ {
+        self.get_all(METADATA_BLOCK_PICTURE).map(|value| {
+            let bytes = BASE64.decode(value.trim()).map_err(|e| {
+                Error::Custom(format!("{METADATA_BLOCK_PICTURE} is not base64: {e}"))
+            })?;
+            Picture::from_bytes(bytes).map_err(|e| e.in_block(Picture::BLOCK_TYPE, None))
+        })
+    }
+    /// Embeds `picture` as a `METADATA_BLOCK_PICTURE` field, keeping the existing ones
+    pub fn add_picture(&mut self, picture: Picture) {
+        let value = BASE64.encode(picture.into_bytes());
+        self.add(METADATA_BLOCK_PICTURE, value);
+    }
+    /// Removes every embedded picture
+    pub fn remove_pictures(&mut self) -> usize {
+        self.remove(METADATA_BLOCK_PICTURE).len()
+    }
+}
+
+impl VorbisComment {
+    /// Parses the block, turning the vendor string and the field names and values into text
+    /// with `decode`
+    pub(crate) fn decode(
+        buf: Vec<u8>,
+        mut decode: impl FnMut(&[u8]) -> String,
+    ) -> crate::Result<Self> {
+        let comment = VorbisCommentRef::from_slice(&buf)?;
+        let vendor = decode(comment.raw_vendor);
+        let comments = comment
+            .raw_fields()
+            .map(|(k, v)| (decode(k), decode(v)))
+            .collect();
         Ok(Self {
-            refer,
-            inner: map,
+            vendor,
+            comments,
             raw: buf,
         })
     }
 
+    /// Whether the vendor and fields still match the bytes the comment was parsed from
+    fn is_unchanged(&self) -> bool {
+        let Ok(parsed) = VorbisCommentRef::from_slice(&self.raw) else {
+            return false;
+        };
+        parsed.vendor() == self.vendor
+            && parsed
+                .iter()
+                .eq(self.iter().map(|(k, v)| (k.into(), v.into())))
+    }
+}
+
+/// A field name with `=` would be split differently when the block is read back
+fn checked_key(key: String) -> String {
+    assert!(
+        VorbisComment::is_valid_key(&key),
+        "invalid Vorbis comment field name {key:?}"
+    );
+    key
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+impl ConvertBytes for VorbisComment {
+    fn from_bytes(buf: Vec<u8>) -> crate::Result<Self> {
+        Self::decode(buf, |text| String::from_utf8_lossy(text).into_owned())
+    }
+
+    /// The parsed bytes if the comment wasn't changed since, so text that isn't UTF-8 and
+    /// entries without `=` are kept
     fn into_bytes(self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        if !self.refer.is_empty() {
-            append(&mut buf, self.refer.as_bytes());
+        if self.is_unchanged() {
+            return self.raw;
         }
-        self.inner.iter().for_each(|(k, v)| {
-            append(&mut buf, format!("{k}={v}").as_bytes())
-        });
+        let mut buf = Vec::new();
+        append(&mut buf, self.vendor.as_bytes());
+        buf.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+        self.comments
+            .iter()
+            .for_each(|(k, v)| append(&mut buf, format!("{k}={v}").as_bytes()));
         buf
     }
 }
 
+/// A VORBIS_COMMENT block read straight from the block payload, fields are decoded on access
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VorbisCommentRef<'a> {
+    vendor: Cow<'a, str>,
+    raw_vendor: &'a [u8],
+    count: u32,
+    /// The length-prefixed fields following the count
+    fields: &'a [u8],
+}
+
+impl<'a> VorbisCommentRef<'a> {
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+    /// All fields in their original order, entries without `=` are skipped
+    pub fn iter(&self) -> impl Iterator<Item = (Cow<'a, str>, Cow<'a, str>)> + 'a {
+        self.raw_fields()
+            .map(|(k, v)| (String::from_utf8_lossy(k), String::from_utf8_lossy(v)))
+    }
+    /// Undecoded names and values of the fields
+    pub(crate) fn raw_fields(&self) -> impl Iterator<Item = (&'a [u8], &'a [u8])> + 'a {
+        let mut stream = Stream::new(self.fields);
+        (0..self.count).filter_map(move |_| {
+            let len = le_u32(stream.take(4).ok()?) as usize;
+            let comment = stream.take(len).ok()?;
+            let eq = comment.iter().position(|b| *b == b'=')?;
+            Some((&comment[..eq], &comment[eq + 1..]))
+        })
+    }
+    /// Undecoded vendor string, field names and values
+    #[cfg(feature = "encoding")]
+    pub(crate) fn raw_texts(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let fields = self.raw_fields().flat_map(|(k, v)| [k, v]);
+        std::iter::once(self.raw_vendor).chain(fields)
+    }
+    /// First value of the field, field names are case-insensitive
+    pub fn get(&self, key: &str) -> Option<Cow<'a, str>> {
+        self.get_all(key).next()
+    }
+    pub fn get_all<'k>(&self, key: &'k str) -> impl Iterator<Item = Cow<'a, str>> + 'k
+    where
+        'a: 'k,
+    {
+        self.iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+    pub fn into_owned(self) -> VorbisComment {
+        let mut comment = VorbisComment::new(self.vendor.as_ref());
+        // names read from a block are kept as they are, even if `add` would reject them
+        comment.comments = self
+            .iter()
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        comment
+    }
+}
+
+impl<'a> BorrowBytes<'a> for VorbisCommentRef<'a> {
+    const BLOCK_TYPE: u8 = 4;
+
+    fn from_slice(buf: &'a [u8]) -> crate::Result<Self> {
+        let mut stream = Stream::new(buf);
+        let vendor_len = le_u32(stream.take(4)?) as usize;
+        let raw_vendor = stream.take(vendor_len)?;
+        let count = le_u32(stream.take(4)?);
+        let start = buf.len() - stream.remaining();
+        for _ in 0..count {
+            let len = le_u32(stream.take(4)?) as usize;
+            stream.take(len)?;
+        }
+        let end = buf.len() - stream.remaining();
+        Ok(Self {
+            vendor: String::from_utf8_lossy(raw_vendor),
+            raw_vendor,
+            count,
+            fields: &buf[start..end],
+        })
+    }
+}
+
+/// Appends a length-prefixed string
 fn append(buf: &mut Vec<u8>, content: &[u8]) {
+    buf.extend_from_slice(&(content.len() as u32).to_le_bytes());
     buf.extend_from_slice(content);
-    buf.push(0);
-    buf.push(0);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_prefixed_fields() {
+        let mut buf = Vec::new();
+        append(&mut buf, b"reference libFLAC 1.4.3 20230623");
+        buf.extend_from_slice(&2u32.to_le_bytes());
+        append(&mut buf, b"TITLE=Caf\xc3\xa9");
+        append(&mut buf, b"ARTIST=Someone");
+        let comment = VorbisComment::from_bytes(buf).unwrap();
+        assert_eq!(comment.vendor(), "reference libFLAC 1.4.3 20230623");
+        assert_eq!(comment.title(), Some("Caf\u{e9}"));
+        assert_eq!(comment.artist(), Some("Someone"));
+
+        let parsed = VorbisComment::from_bytes(comment.clone().into_bytes()).unwrap();
+        assert_eq!(parsed.vendor(), comment.vendor());
+        assert_eq!(parsed.comments(), comment.comments());
+        assert!(VorbisComment::from_bytes(vec![10, 0, 0, 0, b'a']).is_err());
+    }
+
+    #[test]
+    fn multi_valued_fields() {
+        let mut comment = VorbisComment::new("rotic");
+        comment.add("ARTIST", "A");
+        comment.add("TITLE", "\"Quoted\"");
+        comment.add("artist", "B");
+        assert_eq!(comment.get_all("Artist").collect::<Vec<_>>(), ["A", "B"]);
+        assert_eq!(comment.title(), Some("\"Quoted\""));
+
+        comment.set("ARTIST", "C");
+        assert_eq!(comment.iter().collect::<Vec<_>>(), [("ARTIST", "C"), ("TITLE", "\"Quoted\"")]);
+        comment.add("GENRE", "Rock");
+        comment.add("GENRE", "Pop");
+        assert_eq!(comment.remove("genre"), ["Rock", "Pop"]);
+        comment.retain(|k, _| k != "TITLE");
+        assert_eq!(comment.len(), 1);
+
+        comment.set("DATE", "2021-05-04");
+        assert_eq!(comment.year(), Some(2021));
+
+        assert!(VorbisComment::is_valid_key("REPLAYGAIN_TRACK_GAIN"));
+        for key in ["A=B", "TITL\u{c9}", "~TAG", "LINE\n"] {
+            assert!(!VorbisComment::is_valid_key(key));
+            let result = std::panic::catch_unwind(|| VorbisComment::new("").add(key, "x"));
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn borrowed_fields() {
+        let mut buf = Vec::new();
+        append(&mut buf, b"vendor");
+        buf.extend_from_slice(&3u32.to_le_bytes());
+        append(&mut buf, b"ARTIST=One");
+        append(&mut buf, b"no separator");
+        append(&mut buf, b"artist=Tw\xffo");
+        let comment = VorbisCommentRef::from_slice(&buf).unwrap();
+        assert_eq!(comment.vendor(), "vendor");
+        assert!(matches!(comment.get("Artist"), Some(Cow::Borrowed("One"))));
+        let all: Vec<_> = comment.get_all("ARTIST").collect();
+        assert_eq!(all, ["One", "Tw\u{fffd}o"]);
+        assert!(matches!(all[1], Cow::Owned(_)));
+
+        let owned = comment.into_owned();
+        assert_eq!(
+            owned.comments(),
+            VorbisComment::from_bytes(buf.clone()).unwrap().comments()
+        );
+        buf.truncate(buf.len() - 1);
+        assert!(VorbisCommentRef::from_slice(&buf).is_err());
+    }
+
+    #[test]
+    fn embedded_pictures() {
+        let gif = b"GIF89a\x40\x01\xf0\x00\xf7\0\0".to_vec();
+        let mut picture = Picture::from_image_bytes(gif.clone()).unwrap();
+        picture.set_description("cover");
+
+        let mut comment = VorbisComment::new("rotic");
+        comment.add_picture(picture.clone());
+        let value = comment.get(METADATA_BLOCK_PICTURE).unwrap();
+        assert_eq!(BASE64.decode(value).unwrap(), picture.clone().into_bytes());
+
+        let comment = VorbisComment::from_bytes(comment.into_bytes()).unwrap();
+        let pictures: Vec<_> = comment.pictures().collect::<crate::Result<_>>().unwrap();
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].data(), gif);
+        assert_eq!(pictures[0].description(), "cover");
+        assert_eq!(
+            (pictures[0].width(), pictures[0].mime_type()),
+            (320, "image/gif")
+        );
+
+        let mut comment = comment;
+        comment.add(METADATA_BLOCK_PICTURE, "not base64!");
+        assert!(comment.pictures().nth(1).unwrap().is_err());
+        assert_eq!(comment.remove_pictures(), 2);
+        assert_eq!(comment.pictures().count(), 0);
+    }
+}
@@ -0,0 +1,533 @@
+//! FLAC in Ogg, as described by the Ogg FLAC mapping
+//!
+//! The first packet of the logical stream is `\x7FFLAC`, the mapping version, the number of
+//! header packets, `fLaC` and the STREAMINFO block. Every other header packet holds one
+//! metadata block and every audio packet one frame, so concatenating the packets after the
+//! mapping header gives back a native FLAC stream.
+
+use std::io::{self, Read, Seek, SeekFrom};
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+use crate::crc::crc32_update;
+use crate::error::Error::*;
+use crate::Result;
+
+/// Capture pattern starting every Ogg page
+pub const OGG_MAGIC: &[u8; 4] = b"OggS";
+
+const PAGE_HEADER_LEN: usize = 27;
+const CONTINUED: u8 = 0x01;
+const BEGIN_OF_STREAM: u8 = 0x02;
+const END_OF_STREAM: u8 = 0x04;
+
+/// Reads the FLAC logical stream of an Ogg stream as a native FLAC stream.
+///
+/// Pages of other multiplexed logical streams are skipped and page checksums are verified.
+/// Everything that takes a [`Read`] accepts it, e.g. `Decoder::new(OggFlacReader::new(file))`.
+pub struct OggFlacReader<R> {
+    reader: R,
+    pages: FlacPages,
+}
+
+struct Page {
+    flags: u8,
+    serial: u32,
+    body: Vec<u8>,
+}
+
+/// Page handling without the I/O, shared by the sync and async readers
+#[derive(Default)]
+struct FlacPages {
+    serial: Option<u32>,
+    /// Mapping version and number of header packets from the first packet
+    mapping: Option<((u8, u8), u16)>,
+    /// Bytes read so far of the next page
+    raw: Vec<u8>,
+    /// Payload of the current page
+    page: Vec<u8>,
+    pos: usize,
+    /// Offset of the next page
+    offset: u64,
+    end: bool,
+}
+
+enum Step {
+    /// The current page has data left
+    Data,
+    /// This many more bytes of the next page have to be read into `raw`
+    Read(usize),
+    End,
+}
+
+impl<R: Read> OggFlacReader<R> {
+    pub fn new(reader: R) -> OggFlacReader<R> {
+        Self {
+            reader,
+            pages: FlacPages::default(),
+        }
+    }
+}
+
+impl<R> OggFlacReader<R> {
+    /// Serial number of the FLAC logical stream, known after the first read
+    pub fn serial(&self) -> Option<u32> {
+        self.pages.serial
+    }
+    /// Major and minor version of the mapping, known after the first read
+    pub fn mapping_version(&self) -> Option<(u8, u8)> {
+        self.pages.mapping.map(|(version, _)| version)
+    }
+    /// Number of header packets after the first one, known after the first read. The mapping
+    /// allows 0 for "not known in advance"
+    pub fn header_packets(&self) -> Option<u16> {
+        self.pages.mapping.map(|(_, packets)| packets)
+    }
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Length of the page starting with `raw`, as far as the bytes read so far tell
+fn page_len(raw: &[u8]) -> Result<usize> {
+    if raw.len() < PAGE_HEADER_LEN {
+        return Ok(PAGE_HEADER_LEN);
+    }
+    if &raw[..4] != OGG_MAGIC {
+        return Err(InvalidFormat);
+    }
+    if raw[4] != 0 {
+        return Err(Unsupported("Ogg stream structure version"));
+    }
+    let lacing = PAGE_HEADER_LEN + raw[26] as usize;
+    if raw.len() < lacing {
+        return Ok(lacing);
+    }
+    let body: usize = raw[PAGE_HEADER_LEN..lacing]
+        .iter()
+        .map(|n| *n as usize)
+        .sum();
+    Ok(lacing + body)
+}
+
+impl FlacPages {
+    /// What has to happen before the next read
+    fn step(&mut self) -> Result<Step> {
+        while self.pos == self.page.len() {
+            if self.end {
+                return Ok(Step::End);
+            }
+            let len = page_len(&self.raw)?;
+            if self.raw.len() < len {
+                return Ok(Step::Read(len - self.raw.len()));
+            }
+            let page = self.parse_page()?;
+            self.next_page(page)?;
+        }
+        Ok(Step::Data)
+    }
+
+    /// The underlying reader ended while [`Step::Read`] asked for more
+    fn eof(&mut self) -> Result<()> {
+        if !self.raw.is_empty() {
+            return Err(Truncated {
+                offset: self.offset,
+                expected: page_len(&self.raw)? as u64,
+                actual: self.raw.len() as u64,
+            });
+        }
+        if self.serial.is_none() {
+            return Err(Unsupported("Ogg stream without a FLAC logical stream"));
+        }
+        self.end = true;
+        Ok(())
+    }
+
+    /// Copies data of the current page into `buf`
+    fn copy_to(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.page.len() - self.pos);
+        buf[..len].copy_from_slice(&self.page[self.pos..self.pos + len]);
+        self.pos += len;
+        len
+    }
+
+    /// Checks the page in `raw`, which has been read completely
+    fn parse_page(&mut self) -> Result<Page> {
+        let mut raw = std::mem::take(&mut self.raw);
+        let flags = raw[5];
+        let serial = u32::from_le_bytes([raw[14], raw[15], raw[16], raw[17]]);
+        let sequence = u32::from_le_bytes([raw[18], raw[19], raw[20], raw[21]]);
+        let crc = u32::from_le_bytes([raw[22], raw[23], raw[24], raw[25]]);
+        raw[22..26].fill(0);
+        if crc32_update(0, &raw) != crc {
+            return Err(PageCrcMismatch {
+                page: sequence,
+                offset: self.offset,
+            });
+        }
+        self.offset += raw.len() as u64;
+        let body = raw.split_off(PAGE_HEADER_LEN + raw[26] as usize);
+        Ok(Page {
+            flags,
+            serial,
+            body,
+        })
+    }
+
+    /// Makes the page current if it belongs to the FLAC stream
+    fn next_page(&mut self, page: Page) -> Result<()> {
+        match self.serial {
+            Some(serial) if serial == page.serial => {
+                self.end = page.flags & END_OF_STREAM != 0;
+                self.page = page.body;
+                self.pos = 0;
+            }
+            Some(_) => {}
+            None if page.flags & BEGIN_OF_STREAM == 0 => {
+                return Err(Unsupported("Ogg stream without a FLAC logical stream"));
+            }
+            None if page.body.starts_with(b"\x7FFLAC") => self.first_page(page)?,
+            // beginning of another logical stream
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Checks the mapping header, the page must hold exactly the first packet
+    fn first_page(&mut self, page: Page) -> Result<()> {
+        let body = &page.body;
+        if body.len() < 13 || &body[9..13] != b"fLaC" || page.flags & CONTINUED != 0 {
+            return Err(InvalidFormat);
+        }
+        if body[5] != 1 {
+            return Err(Unsupported("Ogg FLAC mapping version"));
+        }
+        self.mapping = Some(((body[5], body[6]), u16::from_be_bytes([body[7], body[8]])));
+        self.serial = Some(page.serial);
+        self.end = page.flags & END_OF_STREAM != 0;
+        self.page = page.body;
+        self.pos = 9;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for OggFlacReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.pages.step().map_err(io::Error::other)? {
+                Step::Data => return Ok(self.pages.copy_to(buf)),
+                Step::Read(len) => {
+                    let raw = &mut self.pages.raw;
+                    if (&mut self.reader).take(len as u64).read_to_end(raw)? == 0 {
+                        self.pages.eof().map_err(io::Error::other)?;
+                    }
+                }
+                Step::End => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Async counterpart of [`OggFlacReader`]
+#[cfg(feature = "async")]
+pub struct AsyncOggFlacReader<R> {
+    reader: R,
+    pages: FlacPages,
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> AsyncOggFlacReader<R> {
+    pub fn new(reader: R) -> AsyncOggFlacReader<R> {
+        Self {
+            reader,
+            pages: FlacPages::default(),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R> AsyncOggFlacReader<R> {
+    /// Serial number of the FLAC logical stream, known after the first read
+    pub fn serial(&self) -> Option<u32> {
+        self.pages.serial
+    }
+    /// Major and minor version of the mapping, known after the first read
+    pub fn mapping_version(&self) -> Option<(u8, u8)> {
+        self.pages.mapping.map(|(version, _)| version)
+    }
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncOggFlacReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            match this.pages.step().map_err(io::Error::other)? {
+                Step::Data => {
+                    let len = this.pages.copy_to(buf.initialize_unfilled());
+                    buf.advance(len);
+                    return Poll::Ready(Ok(()));
+                }
+                Step::Read(len) => {
+                    let raw = &mut this.pages.raw;
+                    let start = raw.len();
+                    raw.resize(start + len, 0);
+                    let mut read = ReadBuf::new(&mut raw[start..]);
+                    let poll = Pin::new(&mut this.reader).poll_read(cx, &mut read);
+                    let filled = read.filled().len();
+                    raw.truncate(start + filled);
+                    if let Poll::Ready(Ok(())) = poll {
+                        if filled == 0 {
+                            this.pages.eof().map_err(io::Error::other)?;
+                        }
+                        continue;
+                    }
+                    return poll;
+                }
+                Step::End => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+/// Ogg FLAC read from the `OggS` found while looking for `fLaC` and the rest of the stream
+pub(crate) type OggSource<R> = OggFlacReader<io::Chain<io::Cursor<[u8; 4]>, R>>;
+#[cfg(feature = "async")]
+pub(crate) type AsyncOggSource<R> = AsyncOggFlacReader<tokio::io::Chain<io::Cursor<[u8; 4]>, R>>;
+
+/// The reader of a decoder, `O` unwraps the FLAC stream if it turned out to be Ogg FLAC
+pub(crate) enum Source<R, O> {
+    Native(R),
+    /// Can't be seeked in
+    Ogg(O),
+}
+
+impl<R: Read, O: Read> Read for Source<R, O> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::Native(reader) => reader.read(buf),
+            Source::Ogg(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R: Seek, O> Seek for Source<R, O> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::Native(reader) => reader.seek(pos),
+            Source::Ogg(_) => Err(io::Error::other(Unsupported("seeking in Ogg FLAC"))),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin, O: AsyncRead + Unpin> AsyncRead for Source<R, O> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Source::Native(reader) => Pin::new(reader).poll_read(cx, buf),
+            Source::Ogg(reader) => Pin::new(reader).poll_read(cx, buf),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncSeek + Unpin, O: Unpin> AsyncSeek for Source<R, O> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match self.get_mut() {
+            Source::Native(reader) => Pin::new(reader).start_seek(position),
+            Source::Ogg(_) => Err(io::Error::other(Unsupported("seeking in Ogg FLAC"))),
+        }
+    }
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match self.get_mut() {
+            Source::Native(reader) => Pin::new(reader).poll_complete(cx),
+            Source::Ogg(_) => Poll::Ready(Ok(0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{StreamInfo, VorbisComment, VorbisCommentRef};
+    use crate::{encode, find_meta, read_from_bytes, read_from_stream, Decoder, EncoderConfig};
+    use crate::{find_meta_ref, Error, MetadataEditor, MetadataReader, Result};
+
+    fn page(flags: u8, serial: u32, sequence: u32, packets: &[&[u8]]) -> Vec<u8> {
+        let mut lacing = Vec::new();
+        for packet in packets {
+            lacing.extend(std::iter::repeat_n(255, packet.len() / 255));
+            lacing.push((packet.len() % 255) as u8);
+        }
+        let mut page = b"OggS\0".to_vec();
+        page.push(flags);
+        page.extend_from_slice(&0u64.to_le_bytes());
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        packets.iter().for_each(|p| page.extend_from_slice(p));
+        let crc = crc32_update(0, &page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    /// Wraps a native stream in Ogg, one page per metadata block and per audio chunk,
+    /// with pages of an unrelated logical stream interleaved
+    fn ogg_flac(native: &[u8]) -> Vec<u8> {
+        let mut reader = MetadataReader::new(native).unwrap();
+        let mut blocks = Vec::new();
+        while let Some(header) = reader.next_header().unwrap() {
+            let mut block = header.to_bytes().to_vec();
+            block.extend(reader.borrow_data().unwrap());
+            blocks.push(block);
+        }
+        let audio = reader.into_inner();
+
+        let mut first = b"\x7FFLAC\x01\x00".to_vec();
+        first.extend_from_slice(&(blocks.len() as u16 - 1).to_be_bytes());
+        first.extend_from_slice(b"fLaC");
+        first.extend_from_slice(&blocks[0]);
+        let mut ogg = page(BEGIN_OF_STREAM, 7, 0, &[&first]);
+        ogg.extend(page(BEGIN_OF_STREAM, 9, 0, &[b"\x01vorbis"]));
+        let mut sequence = 1;
+        for block in &blocks[1..] {
+            ogg.extend(page(0, 7, sequence, &[block]));
+            sequence += 1;
+        }
+        // the reader doesn't look at packet boundaries, fixed size packets will do
+        let chunks: Vec<_> = audio.chunks(1000).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let flags = match i + 1 == chunks.len() {
+                true => END_OF_STREAM,
+                false => 0,
+            };
+            ogg.extend(page(flags, 7, sequence, &[chunk]));
+            ogg.extend(page(0, 9, sequence, &[&[0; 300]]));
+            sequence += 1;
+        }
+        ogg
+    }
+
+    fn streams() -> (Vec<u8>, Vec<u8>) {
+        let samples: Vec<i32> = (0..40_000).map(|i| (i * 37 % 1201) - 600).collect();
+        let native = encode(&samples, EncoderConfig::new(16000, 2, 16)).unwrap();
+        let ogg = ogg_flac(&native);
+        (native, ogg)
+    }
+
+    #[test]
+    fn read_ogg_flac() {
+        let (native, ogg) = streams();
+
+        let mut reader = OggFlacReader::new(&ogg[..]);
+        let mut stream = Vec::new();
+        reader.read_to_end(&mut stream).unwrap();
+        assert_eq!(stream, native);
+        assert_eq!(reader.serial(), Some(7));
+        assert_eq!(reader.mapping_version(), Some((1, 0)));
+
+        let blocks = read_from_stream(&mut &ogg[..]).unwrap();
+        assert_eq!(blocks.len(), read_from_bytes(&native).unwrap().len());
+        assert_eq!(read_from_bytes(&ogg).unwrap().len(), blocks.len());
+        let comment: Option<VorbisComment> = find_meta(&mut &ogg[..]).unwrap();
+        assert!(comment.is_some());
+
+        let decoder = Decoder::new(OggFlacReader::new(&ogg[..])).unwrap();
+        assert_eq!(decoder.stream_info().total_samples, 20_000);
+        let decoded: Result<Vec<_>> = decoder.collect();
+        let expected: Result<Vec<_>> = Decoder::new(&native[..]).unwrap().collect();
+        assert_eq!(decoded.unwrap(), expected.unwrap());
+        let verification = Decoder::new(OggFlacReader::new(&ogg[..]))
+            .unwrap()
+            .verify()
+            .unwrap();
+        assert!(verification.is_valid());
+
+        let mut corrupt = ogg.clone();
+        corrupt[ogg.len() / 2] ^= 1;
+        assert!(find_meta::<StreamInfo, _>(&mut &corrupt[..]).is_ok());
+        let err = Decoder::new(OggFlacReader::new(&corrupt[..]))
+            .unwrap()
+            .verify()
+            .unwrap_err();
+        assert!(matches!(err, PageCrcMismatch { page: _, offset } if offset > 0));
+        let mut cut = OggFlacReader::new(&ogg[..ogg.len() / 2]);
+        let err = Error::from(cut.read_to_end(&mut Vec::new()).unwrap_err());
+        assert!(matches!(err, Truncated { actual, .. } if actual > 0));
+    }
+
+    #[test]
+    fn detect_ogg_flac() {
+        let (native, ogg) = streams();
+        let decoded: Result<Vec<_>> = Decoder::new(&ogg[..]).unwrap().collect();
+        let expected: Result<Vec<_>> = Decoder::new(&native[..]).unwrap().collect();
+        assert_eq!(decoded.unwrap(), expected.unwrap());
+        assert!(Decoder::new(&ogg[..]).unwrap().verify().unwrap().is_valid());
+        let mut decoder = Decoder::new(io::Cursor::new(&ogg)).unwrap();
+        let err = decoder.seek_to_sample(100).unwrap_err();
+        assert!(matches!(err, Unsupported("seeking in Ogg FLAC")));
+
+        let err = MetadataEditor::read_from(&mut &ogg[..]).unwrap_err();
+        assert!(matches!(err, Unsupported("writing Ogg FLAC")));
+        let err = find_meta_ref::<VorbisCommentRef>(&ogg).unwrap_err();
+        assert!(matches!(err, Unsupported(_)));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn detect_ogg_flac_async() {
+        use crate::aysnc_read::*;
+        use tokio::io::AsyncReadExt;
+
+        let (native, ogg) = streams();
+        let mut reader = AsyncOggFlacReader::new(&ogg[..]);
+        let mut stream = Vec::new();
+        reader.read_to_end(&mut stream).await.unwrap();
+        assert_eq!(stream, native);
+        assert_eq!(reader.serial(), Some(7));
+
+        let blocks = read_from_async_stream(&mut &ogg[..]).await.unwrap();
+        assert_eq!(blocks.len(), read_from_bytes(&native).unwrap().len());
+        let comment: Option<VorbisComment> = find_meta_async(&mut &ogg[..]).await.unwrap();
+        assert!(comment.is_some());
+        assert!(verify_async_stream(&mut &ogg[..]).await.unwrap().is_valid());
+
+        let mut decoder = AsyncDecoder::new(io::Cursor::new(&ogg)).await.unwrap();
+        let mut expected = Decoder::new(&native[..]).unwrap();
+        while let Some(block) = decoder.read_frame().await.unwrap() {
+            assert_eq!(block, expected.next().unwrap().unwrap());
+        }
+        assert!(expected.next().is_none());
+        let err = decoder.seek_to_sample(100).await.unwrap_err();
+        assert!(matches!(err, Unsupported("seeking in Ogg FLAC")));
+
+        let err = MetadataEditor::read_from_async(&mut &ogg[..])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Unsupported("writing Ogg FLAC")));
+    }
+}
@@ -1,37 +1,287 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::Path;
 
-use crate::const_array;
 use crate::error::Error::*;
-use crate::metadata::{Block, BlockBytes, BlockType};
-use crate::Result;
+use crate::id3::Id3v2Tag;
+use crate::metadata::{BlockBytes, BlockHeader, BlockType, BorrowBytes};
+use crate::ogg::{OggFlacReader, OGG_MAGIC};
+use crate::{const_array, Result};
 
-pub fn read_from_stream<R: Read>(buf: &mut R) -> Result<Vec<BlockBytes>> {
-    let mut blocks = Vec::new();
-    let mut four = [0; 4];
-    buf.read_exact(&mut four)?;
-    if &four != b"fLaC" {
-        return Err(InvalidFormat);
+/// Reads in front of the first block header
+enum Head {
+    Start,
+    /// `fLaC`, or the start of an ID3v2 tag if there was none yet
+    Marker,
+    Id3v2Header,
+    /// An ID3v2 tag of this length
+    Id3v2(usize),
+}
+
+impl Head {
+    /// Bytes read once this part is complete
+    fn len(&self) -> usize {
+        match self {
+            Head::Start => 0,
+            Head::Marker => 4,
+            Head::Id3v2Header => Id3v2Tag::HEADER_LEN,
+            Head::Id3v2(len) => *len,
+        }
     }
+}
+
+/// Header parsing and payload accounting of a metadata reader without the I/O, shared by the
+/// sync and async readers
+pub(crate) struct MetadataState {
+    /// Bytes of the current part read in front of the first block header
+    head: Vec<u8>,
+    part: Head,
+    id3v2: Option<Id3v2Tag>,
+    /// Unread payload bytes of the current block
+    remaining: u32,
+    /// Offset of the next unread byte
+    position: u64,
+    done: bool,
+    /// Offset of the end of the stream, looked up by the first skip
+    end: Option<u64>,
+}
 
-    loop {
-        buf.read_exact(&mut four)?;
-        let end = (four[0] & 0x80) == 128;
-        let ty = four[0] & 0x7F;
-        let size = u32::from_be_bytes(const_array!(@start(0), four => 1, 2, 3));
-        let mut block_buf = Vec::new();
-        buf.take(size as u64).read_to_end(&mut block_buf)?;
-        if block_buf.len() != size as usize {
-            return Err(InvalidFormat);
+impl MetadataState {
+    pub(crate) fn new() -> MetadataState {
+        Self {
+            head: Vec::new(),
+            part: Head::Start,
+            id3v2: None,
+            remaining: 0,
+            position: 4,
+            done: false,
+            end: None,
         }
-        blocks.push(Block::new(end, ty, size, block_buf));
-        if end {
-            break;
+    }
+    /// Checks what was read in front of the first block header, returns the buffer and how
+    /// many bytes to append to it, `None` once the `fLaC` marker was found
+    pub(crate) fn handshake(&mut self) -> Result<Option<(&mut Vec<u8>, u64)>> {
+        if self.head.len() < self.part.len() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
         }
+        self.part = match self.part {
+            Head::Start => Head::Marker,
+            Head::Marker if self.id3v2.is_none() && Id3v2Tag::is_header(&self.head) => {
+                Head::Id3v2Header
+            }
+            Head::Marker => {
+                let four = const_array!(self.head, 0, 4);
+                if &four != b"fLaC" {
+                    return Err(BadMagic(four));
+                }
+                return Ok(None);
+            }
+            Head::Id3v2Header => {
+                let header = const_array!(self.head, 0, Id3v2Tag::HEADER_LEN);
+                Head::Id3v2(Id3v2Tag::tag_len(&header)?)
+            }
+            Head::Id3v2(_) => {
+                self.id3v2 = Some(Id3v2Tag::from_bytes(std::mem::take(&mut self.head))?);
+                Head::Marker
+            }
+        };
+        let len = (self.part.len() - self.head.len()) as u64;
+        Ok(Some((&mut self.head, len)))
+    }
+    /// Takes the length of the unread payload of the current block
+    pub(crate) fn take_remaining(&mut self) -> u64 {
+        std::mem::take(&mut self.remaining) as u64
+    }
+    /// Advances past `actual` payload bytes, failing if the block was cut short
+    pub(crate) fn consumed(&mut self, expected: u64, actual: u64) -> Result<()> {
+        let offset = self.position;
+        self.position += actual;
+        if actual != expected {
+            return Err(Truncated {
+                offset,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+    /// Whether the last block header was read
+    pub(crate) fn done(&self) -> bool {
+        self.done
+    }
+    /// Ends the reading, after an error
+    pub(crate) fn stop(&mut self) {
+        self.done = true;
     }
+    /// Takes in the next block header
+    pub(crate) fn header(&mut self, four: [u8; 4]) -> BlockHeader {
+        self.position += 4;
+        let header = BlockHeader::from_bytes(four);
+        self.done = header.last_metadata_block;
+        self.remaining = header.block_size;
+        header
+    }
+    /// Whether skipping has to look up the end of the stream first
+    pub(crate) fn needs_end(&self) -> bool {
+        self.end.is_none()
+    }
+    /// Records that `left` bytes follow the position in the stream
+    pub(crate) fn set_end(&mut self, left: u64) {
+        self.end = Some(self.position + left);
+    }
+    /// Takes the unread payload of the current block, returns its length and how much of it
+    /// the stream holds since seeking past the end succeeds
+    pub(crate) fn skip(&mut self) -> (u64, u64) {
+        let remaining = self.take_remaining();
+        let end = self.end.unwrap_or(u64::MAX);
+        (remaining, remaining.min(end.saturating_sub(self.position)))
+    }
+    /// Offset of the next unread byte from the `fLaC` marker
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+    pub(crate) fn id3v2(&self) -> Option<&Id3v2Tag> {
+        self.id3v2.as_ref()
+    }
+    pub(crate) fn take_id3v2(&mut self) -> Option<Id3v2Tag> {
+        self.id3v2.take()
+    }
+}
 
-    Ok(blocks)
+/// Reads metadata block headers one at a time, a payload is only read when asked for.
+///
+/// The payload of a block that is neither read nor skipped is discarded by the next call to
+/// [`next_header`](Self::next_header). Once it returns `None` the reader is at the first frame.
+pub struct MetadataReader<R> {
+    reader: R,
+    state: MetadataState,
+}
+
+impl<R: Read> MetadataReader<R> {
+    /// Checks the `fLaC` marker, leaving `reader` at the first block header.
+    ///
+    /// An ID3v2 tag in front of the marker is skipped and kept, see [`id3v2`](Self::id3v2).
+    pub fn new(mut reader: R) -> Result<MetadataReader<R>> {
+        let mut state = MetadataState::new();
+        while let Some((buf, len)) = state.handshake()? {
+            (&mut reader).take(len).read_to_end(buf)?;
+        }
+        Ok(Self { reader, state })
+    }
+    /// Header of the next block, `None` after the last one
+    pub fn next_header(&mut self) -> Result<Option<BlockHeader>> {
+        let remaining = self.state.take_remaining();
+        let skipped = io::copy(&mut (&mut self.reader).take(remaining), &mut io::sink())?;
+        self.state.consumed(remaining, skipped)?;
+        if self.state.done() {
+            return Ok(None);
+        }
+        let mut four = [0; 4];
+        self.reader.read_exact(&mut four)?;
+        Ok(Some(self.state.header(four)))
+    }
+    /// Payload of the current block
+    pub fn read_data(&mut self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let remaining = self.state.take_remaining();
+        (&mut self.reader).take(remaining).read_to_end(&mut buf)?;
+        self.state.consumed(remaining, buf.len() as u64)?;
+        Ok(buf)
+    }
+    pub fn read_block<B: BlockType>(&mut self) -> Result<B> {
+        let offset = self.state.position();
+        B::from_bytes(self.read_data()?).map_err(|e| e.in_block(B::BLOCK_TYPE, Some(offset)))
+    }
+    /// Offset of the next unread byte from the `fLaC` marker
+    pub fn position(&self) -> u64 {
+        self.state.position()
+    }
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> MetadataReader<R> {
+    /// ID3v2 tag found in front of the `fLaC` marker
+    pub fn id3v2(&self) -> Option<&Id3v2Tag> {
+        self.state.id3v2()
+    }
+    pub fn take_id3v2(&mut self) -> Option<Id3v2Tag> {
+        self.state.take_id3v2()
+    }
+}
+
+impl<R: Read + Seek> MetadataReader<R> {
+    /// Moves past the payload of the current block without reading it
+    pub fn skip_data(&mut self) -> Result<()> {
+        if self.state.needs_end() {
+            let start = self.reader.stream_position()?;
+            let end = self.reader.seek(SeekFrom::End(0))?;
+            self.reader.seek(SeekFrom::Start(start))?;
+            self.state.set_end(end.saturating_sub(start));
+        }
+        let (remaining, skipped) = self.state.skip();
+        self.reader.seek(SeekFrom::Current(skipped as i64))?;
+        self.state.consumed(remaining, skipped)
+    }
+}
+
+impl<'a> MetadataReader<&'a [u8]> {
+    /// Payload of the current block, borrowed from the buffer
+    pub fn borrow_data(&mut self) -> Result<&'a [u8]> {
+        let remaining = self.state.take_remaining() as usize;
+        let len = remaining.min(self.reader.len());
+        self.state.consumed(remaining as u64, len as u64)?;
+        let (data, rest) = self.reader.split_at(len);
+        self.reader = rest;
+        Ok(data)
+    }
+    pub fn borrow_block<B: BorrowBytes<'a>>(&mut self) -> Result<B> {
+        let offset = self.state.position();
+        B::from_slice(self.borrow_data()?).map_err(|e| e.in_block(B::BLOCK_TYPE, Some(offset)))
+    }
+}
+
+impl<R: Read> Iterator for MetadataReader<R> {
+    type Item = Result<BlockHeader>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.next_header();
+        if header.is_err() {
+            self.state.stop();
+        }
+        header.transpose()
+    }
+}
+
+/// Runs `read` on `buf`, or on the FLAC stream inside it if it turns out to be Ogg FLAC
+fn native_or_ogg<R: Read, T>(buf: &mut R, read: impl Fn(&mut dyn Read) -> Result<T>) -> Result<T> {
+    match read(buf) {
+        Err(BadMagic(magic)) if &magic == OGG_MAGIC => {
+            read(&mut OggFlacReader::new((&magic[..]).chain(buf)))
+        }
+        result => result,
+    }
+}
+
+/// Reads the metadata blocks of a native or Ogg FLAC stream
+pub fn read_from_stream<R: Read>(buf: &mut R) -> Result<Vec<BlockBytes>> {
+    native_or_ogg(buf, |buf| read_with_id3v2(buf).map(|(_, blocks)| blocks))
+}
+
+/// Reads the metadata blocks along with the ID3v2 tag in front of `fLaC`, if any
+pub fn read_with_id3v2<R: Read + ?Sized>(
+    buf: &mut R,
+) -> Result<(Option<Id3v2Tag>, Vec<BlockBytes>)> {
+    let mut reader = MetadataReader::new(buf)?;
+    let mut blocks = Vec::new();
+    while let Some(header) = reader.next_header()? {
+        blocks.push(header.into_block(reader.read_data()?));
+    }
+    Ok((reader.take_id3v2(), blocks))
 }
 
 pub fn read_from_path<R: Read>(path: impl AsRef<Path>) -> Result<Vec<BlockBytes>> {
@@ -39,94 +289,292 @@ pub fn read_from_path<R: Read>(path: impl AsRef<Path>) -> Result<Vec<BlockBytes>
 }
 
 pub fn read_from_bytes(buf: &[u8]) -> Result<Vec<BlockBytes>> {
-    let mut blocks = Vec::new();
-    let mut stream = Stream::new(buf);
-    if stream.take(4)? != b"fLaC" {
-        return Err(InvalidFormat);
+    if buf.starts_with(OGG_MAGIC) {
+        return read_from_stream(&mut &buf[..]);
     }
-    loop {
-        let four = stream.take(4)?;
-        let end = (four[0] & 0x80) == 128;
-        let ty = four[0] & 0x7F;
-        let size = u32::from_be_bytes(const_array!(@start(0), four => 1, 2, 3));
-        let block_buf = stream.take(size as usize)?;
-        blocks.push(Block::new(end, ty, size, block_buf.to_owned()));
-        if end {
-            break;
-        }
+    let mut reader = MetadataReader::new(buf)?;
+    let mut blocks = Vec::new();
+    while let Some(header) = reader.next_header()? {
+        blocks.push(header.into_block(reader.borrow_data()?.to_owned()));
     }
-
     Ok(blocks)
 }
 
 pub fn find_meta<B: BlockType, R: Read>(buf: &mut R) -> Result<Option<B>> {
-    let mut four = [0; 4];
-    buf.read_exact(&mut four)?;
-    if &four != b"fLaC" {
-        return Err(InvalidFormat);
-    }
-
-    loop {
-        buf.read_exact(&mut four)?;
-        let end = (four[0] & 0x80) == 128;
-        let ty = four[0] & 0x7F;
-        let size = u32::from_be_bytes(const_array!(@start(0), four => 1, 2, 3));
-        let mut block_buf = Vec::new();
-        buf.take(size as u64).read_to_end(&mut block_buf)?;
-        if block_buf.len() != size as usize {
-            return Err(InvalidFormat);
+    native_or_ogg(buf, |buf| {
+        let mut reader = MetadataReader::new(buf)?;
+        while let Some(header) = reader.next_header()? {
+            if header.is::<B>() {
+                return reader.read_block().map(Some);
+            }
         }
-        if B::BLOCK_TYPE == ty {
-            return Ok(Some(B::from_bytes(block_buf)?));
+        Ok(None)
+    })
+}
+
+/// Like [`find_meta`], seeking past the payloads of other blocks rather than reading them
+pub fn find_meta_seek<B: BlockType, R: Read + Seek>(buf: &mut R) -> Result<Option<B>> {
+    let mut reader = match MetadataReader::new(&mut *buf) {
+        Err(BadMagic(magic)) if &magic == OGG_MAGIC => {
+            return find_meta(&mut (&magic[..]).chain(buf));
         }
-        if end {
-            break Ok(None);
+        reader => reader?,
+    };
+    while let Some(header) = reader.next_header()? {
+        if header.is::<B>() {
+            return reader.read_block().map(Some);
         }
+        reader.skip_data()?;
     }
+    Ok(None)
+}
+
+/// Finds the first block of type `B` in a file without reading the payloads of other blocks,
+/// such as large pictures
+pub fn find_meta_from_path<B: BlockType>(path: impl AsRef<Path>) -> Result<Option<B>> {
+    find_meta_seek(&mut io::BufReader::new(File::open(path)?))
 }
 
 pub fn find_meta_from_bytes<B: BlockType>(bytes: &[u8]) -> Result<Option<B>> {
-    let mut stream = Stream::new(bytes);
-    if stream.take(4)? != b"fLaC" {
-        return Err(InvalidFormat);
+    if bytes.starts_with(OGG_MAGIC) {
+        return find_meta(&mut &bytes[..]);
     }
-
-    loop {
-        let four = stream.take(4)?;
-        let end = (four[0] & 0x80) == 128;
-        let ty = four[0] & 0x7F;
-        let size = u32::from_be_bytes(const_array!(@start(0), four => 1, 2, 3));
-        let block_buf = stream.take(size as usize)?;
-        if block_buf.len() != size as usize {
-            return Err(InvalidFormat);
+    let mut reader = MetadataReader::new(bytes)?;
+    while let Some(header) = reader.next_header()? {
+        if header.is::<B>() {
+            return reader.read_block().map(Some);
         }
-        if B::BLOCK_TYPE == ty {
-            println!("3333---");
-            return Ok(Some(B::from_bytes(block_buf.to_owned())?));
-        }
-        if end {
-            break Ok(None);
+    }
+    Ok(None)
+}
+
+/// Parses the first block of type `B` without copying it out of `bytes`, only for native
+/// streams since Ogg pages split the blocks
+pub fn find_meta_ref<'a, B: BorrowBytes<'a>>(bytes: &'a [u8]) -> Result<Option<B>> {
+    if bytes.starts_with(OGG_MAGIC) {
+        return Err(Unsupported("borrowing blocks from Ogg FLAC"));
+    }
+    let mut reader = MetadataReader::new(bytes)?;
+    while let Some(header) = reader.next_header()? {
+        if header.block_type == B::BLOCK_TYPE {
+            return reader.borrow_block().map(Some);
         }
     }
+    Ok(None)
 }
+
 pub(crate) struct Stream<'a> {
     inner: &'a [u8],
     index: usize,
 }
 
 impl<'a> Stream<'a> {
-    pub(crate) fn new(buf: &[u8]) -> Stream {
+    pub(crate) fn new(buf: &[u8]) -> Stream<'_> {
         Stream {
             inner: buf,
             index: 0,
         }
     }
+    pub(crate) fn remaining(&self) -> usize {
+        self.inner.len().saturating_sub(self.index)
+    }
     pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8]> {
         let start = self.index;
-        self.index += n;
-        if self.index > self.inner.len() {
-            return Err(InvalidFormat);
+        if n > self.remaining() {
+            return Err(Truncated {
+                offset: start as u64,
+                expected: n as u64,
+                actual: self.remaining() as u64,
+            });
         }
+        self.index += n;
         Ok(&self.inner[start..self.index])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::metadata::{
+        Block, PictureRef, PictureType, StreamInfo, VorbisComment, VorbisCommentRef,
+    };
+    use crate::{encode, Decoder, EncoderConfig, MetadataEditor};
+
+    /// Counts the bytes actually read and the seeks
+    struct Counting(Cursor<Vec<u8>>, usize, usize);
+
+    impl Read for Counting {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.read(buf)?;
+            self.1 += n;
+            Ok(n)
+        }
+    }
+    impl Seek for Counting {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.2 += 1;
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn lazy_blocks() {
+        let mut editor = MetadataEditor::new(StreamInfo {
+            channels: 2,
+            bps: 16,
+            sample_rate: 44100,
+            ..Default::default()
+        });
+        let picture = vec![0x55; 1 << 20];
+        editor
+            .blocks_mut()
+            .push(Block::new(false, 6, picture.len() as u32, picture));
+        let mut comment = VorbisComment::new("test");
+        comment.add("TITLE", "lazy");
+        editor.push(comment);
+        let mut bytes = editor.to_bytes(None).unwrap();
+        bytes.extend([0xFF, 0xF8]);
+
+        let mut reader = MetadataReader::new(Counting(Cursor::new(bytes.clone()), 0, 0)).unwrap();
+        let mut title = None;
+        while let Some(header) = reader.next_header().unwrap() {
+            if header.is::<VorbisComment>() {
+                let comment: VorbisComment = reader.read_block().unwrap();
+                title = comment.title().map(str::to_owned);
+            } else {
+                reader.skip_data().unwrap();
+            }
+        }
+        assert_eq!(title.as_deref(), Some("lazy"));
+        let mut inner = reader.into_inner();
+        assert!(inner.1 < 200);
+        // the end is looked up once, the other skips are a single seek
+        assert_eq!(inner.2, 4 + 1);
+        let mut sync = [0; 2];
+        inner.read_exact(&mut sync).unwrap();
+        assert_eq!(sync, [0xFF, 0xF8]);
+
+        let types: Vec<u8> = MetadataReader::new(&bytes[..])
+            .unwrap()
+            .map(|h| h.unwrap().block_type)
+            .collect();
+        assert_eq!(types, [0, 6, 4]);
+
+        let mut reader = MetadataReader::new(&bytes[..]).unwrap();
+        reader.nth(1).unwrap().unwrap();
+        let data = reader.borrow_data().unwrap();
+        assert_eq!(data.as_ptr(), bytes[4 + 4 + 34 + 4..].as_ptr());
+        assert_eq!(data.len(), 1 << 20);
+
+        let blocks = read_from_bytes(&bytes).unwrap();
+        assert_eq!(read_from_stream(&mut &bytes[..]).unwrap().len(), 3);
+        assert_eq!(blocks[1].header(), BlockHeader::from_bytes([6, 0x10, 0, 0]));
+        assert!(find_meta::<VorbisComment, _>(&mut &bytes[..])
+            .unwrap()
+            .is_some());
+        assert!(find_meta_from_bytes::<VorbisComment>(&bytes)
+            .unwrap()
+            .is_some());
+
+        let mut counting = Counting(Cursor::new(bytes.clone()), 0, 0);
+        let comment: VorbisComment = find_meta_seek(&mut counting).unwrap().unwrap();
+        assert_eq!(comment.title(), Some("lazy"));
+        assert!(counting.1 < 200);
+        let path = std::env::temp_dir().join(format!("rotic-read-{}.flac", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let comment = find_meta_from_path::<VorbisComment>(&path).unwrap();
+        assert_eq!(comment.unwrap().title(), Some("lazy"));
+        std::fs::remove_file(&path).unwrap();
+
+        let mut reader = MetadataReader::new(Cursor::new(&bytes[..100_000])).unwrap();
+        reader.nth(1).unwrap().unwrap();
+        assert!(matches!(
+            reader.skip_data(),
+            Err(Truncated {
+                offset: 46,
+                expected: 0x10_0000,
+                actual: 99_954
+            })
+        ));
+        assert!(find_meta_seek::<VorbisComment, _>(&mut Cursor::new(&bytes[..100_000])).is_err());
+    }
+
+    #[test]
+    fn borrowed_blocks() {
+        let mut picture = vec![0, 0, 0, 3];
+        for field in [&b"image/png"[..], b"front"] {
+            picture.extend((field.len() as u32).to_be_bytes());
+            picture.extend(field);
+        }
+        [16, 16, 24, 0, 4]
+            .iter()
+            .for_each(|n: &u32| picture.extend(n.to_be_bytes()));
+        picture.extend(b"\x89PNG");
+
+        let mut editor = MetadataEditor::new(StreamInfo {
+            channels: 1,
+            bps: 8,
+            sample_rate: 8000,
+            ..Default::default()
+        });
+        editor
+            .blocks_mut()
+            .push(Block::new(false, 6, picture.len() as u32, picture));
+        let bytes = editor.to_bytes(None).unwrap();
+
+        let picture: PictureRef = find_meta_ref(&bytes).unwrap().unwrap();
+        assert_eq!(picture.picture_type(), PictureType::CoverFont);
+        assert_eq!(
+            (picture.mime_type(), picture.description()),
+            ("image/png", "front")
+        );
+        assert_eq!(picture.data(), b"\x89PNG");
+        assert!(bytes.as_ptr_range().contains(&picture.data().as_ptr()));
+        assert_eq!(picture.clone().into_owned().width(), 16);
+        assert!(find_meta_ref::<VorbisCommentRef>(&bytes).unwrap().is_none());
+    }
+
+    #[test]
+    fn id3v2_prefix() {
+        let samples: Vec<i32> = (0..50_000).map(|i| (i * 31 % 997) - 498).collect();
+        let flac = encode(&samples, EncoderConfig::new(8000, 1, 16)).unwrap();
+        let tag = crate::id3::tests::tag(true);
+        let bytes = [&tag[..], &flac].concat();
+
+        let data = |blocks: Vec<BlockBytes>| -> Vec<Vec<u8>> {
+            blocks.into_iter().map(|b| b.block_data().clone()).collect()
+        };
+        let (id3v2, blocks) = read_with_id3v2(&mut &bytes[..]).unwrap();
+        assert_eq!(id3v2.unwrap().as_bytes(), tag);
+        let blocks = data(blocks);
+        assert_eq!(blocks, data(read_from_stream(&mut &flac[..]).unwrap()));
+        assert_eq!(data(read_from_bytes(&bytes).unwrap()), blocks);
+        assert!(find_meta::<StreamInfo, _>(&mut &bytes[..])
+            .unwrap()
+            .is_some());
+        assert!(find_meta_ref::<VorbisCommentRef>(&bytes).unwrap().is_some());
+        let reader = MetadataReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.position(), 4);
+        assert!(reader.id3v2().unwrap().body().starts_with(b"TIT2"));
+
+        let (offset, _) = crate::frame::find_first_frame(&mut &bytes[..]).unwrap();
+        let (plain, _) = crate::frame::find_first_frame(&mut &flac[..]).unwrap();
+        assert_eq!(offset, plain + tag.len() as u64);
+        let mut decoder = Decoder::new(Cursor::new(&bytes)).unwrap();
+        assert!(decoder.id3v2().is_some());
+        let block = decoder.seek_to_sample(20_000).unwrap().unwrap();
+        assert_eq!(block.first_sample(), 20_000);
+
+        let mut editor = MetadataEditor::read_from(&mut &bytes[..]).unwrap();
+        let mut out = Vec::new();
+        editor.write_to(&mut Cursor::new(&bytes), &mut out).unwrap();
+        assert_eq!(out, bytes);
+        editor.set_id3v2(None);
+        out.clear();
+        editor.write_to(&mut Cursor::new(&bytes), &mut out).unwrap();
+        assert!(out.starts_with(b"fLaC"));
+        assert_eq!(data(read_from_bytes(&out).unwrap())[..2], blocks[..2]);
+    }
+}
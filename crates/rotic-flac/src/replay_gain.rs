@@ -0,0 +1,408 @@
+//! ReplayGain 2.0: loudness measured as in EBU R128 / ITU-R BS.1770 against a -18 LUFS
+//! reference, peaks are sample peaks
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::error::Error::*;
+use crate::metadata::VorbisComment;
+use crate::{Decoder, MetadataEditor, Result};
+
+/// Loudness ReplayGain 2.0 normalizes to
+pub const REFERENCE_LOUDNESS: f64 = -18.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+pub const TRACK_GAIN: &str = "REPLAYGAIN_TRACK_GAIN";
+pub const TRACK_PEAK: &str = "REPLAYGAIN_TRACK_PEAK";
+pub const ALBUM_GAIN: &str = "REPLAYGAIN_ALBUM_GAIN";
+pub const ALBUM_PEAK: &str = "REPLAYGAIN_ALBUM_PEAK";
+pub const REFERENCE: &str = "REPLAYGAIN_REFERENCE_LOUDNESS";
+
+/// Gain in dB to reach the reference loudness and the peak as a fraction of full scale
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayGain {
+    pub gain: f64,
+    pub peak: f64,
+}
+
+/// Second order IIR filter, transposed direct form II
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two K-weighting stages of BS.1770 for `sample_rate`: a high shelf modelling the head
+/// and a high pass
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let k = (std::f64::consts::PI * 1681.974450955533 / fs).tan();
+    let (q, vh) = (0.7071752369554196, 10f64.powf(3.999843853973347 / 20.0));
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let k = (std::f64::consts::PI * 38.13547087602444 / fs).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    [shelf, high_pass]
+}
+
+/// BS.1770 weight of each channel in the FLAC channel order, surround channels count more
+/// and LFE not at all
+fn channel_weights(channels: usize) -> Vec<f64> {
+    let weights: &[f64] = match channels {
+        4 => &[1.0, 1.0, 1.41, 1.41],
+        5 => &[1.0, 1.0, 1.0, 1.41, 1.41],
+        6 => &[1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+        7 => &[1.0, 1.0, 1.0, 0.0, 1.41, 1.41, 1.41],
+        8 => &[1.0, 1.0, 1.0, 0.0, 1.41, 1.41, 1.41, 1.41],
+        _ => &[1.0; 3],
+    };
+    weights[..channels.min(weights.len())].to_vec()
+}
+
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Integrated loudness of the gating block energies, `None` if every block is gated out
+fn gated_loudness<'a>(blocks: impl Iterator<Item = &'a f64> + Clone) -> Option<f64> {
+    let mean = |blocks: &mut dyn Iterator<Item = &f64>| {
+        let (sum, count) = blocks.fold((0.0, 0), |(sum, count), e| (sum + e, count + 1));
+        (count > 0).then(|| sum / count as f64)
+    };
+    let audible = blocks.filter(|e| loudness(**e) > ABSOLUTE_GATE);
+    let threshold = loudness(mean(&mut audible.clone())?) + RELATIVE_GATE;
+    mean(&mut audible.filter(|e| loudness(**e) > threshold)).map(loudness)
+}
+
+/// Measures the loudness and peak of interleaved samples
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Full scale of the samples
+    scale: f64,
+    /// Samples per channel in 100 ms
+    step_len: usize,
+    /// Sum of squares of the current 100 ms step, per channel
+    sums: Vec<f64>,
+    step_pos: usize,
+    /// Weighted mean squares of the last steps, a gating block is four of them
+    steps: Vec<f64>,
+    loudness: Loudness,
+}
+
+/// Result of a [`LoudnessMeter`], can be combined with other tracks for album gain
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Loudness {
+    /// Mean square of every 400 ms gating block, overlapping by 75%
+    blocks: Vec<f64>,
+    peak: f64,
+}
+
+impl LoudnessMeter {
+    /// # Panics
+    ///
+    /// If `channels` is 0
+    pub fn new(sample_rate: u32, channels: usize, bits_per_sample: u8) -> LoudnessMeter {
+        assert!(channels >= 1, "a loudness meter needs at least one channel");
+        Self {
+            filters: vec![k_weighting(sample_rate); channels],
+            weights: channel_weights(channels),
+            scale: (1u64 << (bits_per_sample.max(1) - 1)) as f64,
+            step_len: (sample_rate as usize).div_ceil(10).max(1),
+            sums: vec![0.0; channels],
+            step_pos: 0,
+            steps: Vec::with_capacity(4),
+            loudness: Loudness::default(),
+        }
+    }
+    /// Adds samples interleaved by channel
+    pub fn push(&mut self, samples: &[i32]) {
+        for frame in samples.chunks_exact(self.filters.len()) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let x = *sample as f64 / self.scale;
+                self.loudness.peak = self.loudness.peak.max(x.abs());
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let y = high_pass.process(shelf.process(x));
+                self.sums[channel] += y * y;
+            }
+            self.step_pos += 1;
+            if self.step_pos == self.step_len {
+                self.end_step();
+            }
+        }
+    }
+    fn end_step(&mut self) {
+        let len = self.step_len as f64;
+        let energy = self
+            .sums
+            .iter_mut()
+            .zip(&self.weights)
+            .map(|(sum, weight)| weight * std::mem::take(sum) / len)
+            .sum();
+        self.step_pos = 0;
+        if self.steps.len() == 4 {
+            self.steps.remove(0);
+        }
+        self.steps.push(energy);
+        if self.steps.len() == 4 {
+            self.loudness
+                .blocks
+                .push(self.steps.iter().sum::<f64>() / 4.0);
+        }
+    }
+    /// Drops the incomplete last step
+    pub fn finish(self) -> Loudness {
+        self.loudness
+    }
+}
+
+impl Loudness {
+    /// Decodes `reader` to measure it
+    pub fn measure<R: Read>(reader: R) -> Result<Loudness> {
+        let mut decoder = Decoder::new(reader)?;
+        let info = decoder.stream_info();
+        let mut meter = LoudnessMeter::new(info.sample_rate, info.channels as usize, info.bps);
+        while let Some(block) = decoder.read_frame()? {
+            meter.push(block.samples());
+        }
+        Ok(meter.finish())
+    }
+    pub fn measure_path(path: impl AsRef<Path>) -> Result<Loudness> {
+        Self::measure(BufReader::new(File::open(path)?))
+    }
+    /// Combines tracks as if they were played back to back
+    pub fn album<'a>(tracks: impl IntoIterator<Item = &'a Loudness>) -> Loudness {
+        tracks
+            .into_iter()
+            .fold(Loudness::default(), |mut album, track| {
+                album.blocks.extend_from_slice(&track.blocks);
+                album.peak = album.peak.max(track.peak);
+                album
+            })
+    }
+    /// Integrated loudness in LUFS, `None` for silence or less than 400 ms of audio
+    pub fn integrated(&self) -> Option<f64> {
+        gated_loudness(self.blocks.iter())
+    }
+    /// Sample peak as a fraction of full scale
+    pub fn peak(&self) -> f64 {
+        self.peak
+    }
+    /// Gain and peak, the gain is 0 dB when there's no loudness to measure
+    pub fn replay_gain(&self) -> ReplayGain {
+        ReplayGain {
+            gain: self.integrated().map_or(0.0, |l| REFERENCE_LOUDNESS - l),
+            peak: self.peak,
+        }
+    }
+}
+
+impl VorbisComment {
+    /// Sets the `REPLAYGAIN_*` fields, the album ones are removed when `album` is `None`
+    pub fn set_replay_gain(&mut self, track: ReplayGain, album: Option<ReplayGain>) {
+        self.set(TRACK_GAIN, format!("{:.2} dB", track.gain));
+        self.set(TRACK_PEAK, format!("{:.6}", track.peak));
+        match album {
+            Some(album) => {
+                self.set(ALBUM_GAIN, format!("{:.2} dB", album.gain));
+                self.set(ALBUM_PEAK, format!("{:.6}", album.peak));
+            }
+            None => {
+                self.remove(ALBUM_GAIN);
+                self.remove(ALBUM_PEAK);
+            }
+        }
+        self.set(REFERENCE, format!("{REFERENCE_LOUDNESS:.2} LUFS"));
+    }
+    pub fn track_replay_gain(&self) -> Option<ReplayGain> {
+        parse_replay_gain(self.get(TRACK_GAIN)?, self.get(TRACK_PEAK)?)
+    }
+    pub fn album_replay_gain(&self) -> Option<ReplayGain> {
+        parse_replay_gain(self.get(ALBUM_GAIN)?, self.get(ALBUM_PEAK)?)
+    }
+}
+
+fn parse_replay_gain(gain: &str, peak: &str) -> Option<ReplayGain> {
+    let gain = gain.trim();
+    let gain = gain.strip_suffix("dB").unwrap_or(gain).trim();
+    Some(ReplayGain {
+        gain: gain.parse().ok()?,
+        peak: peak.trim().parse().ok()?,
+    })
+}
+
+impl MetadataEditor {
+    /// Writes the ReplayGain fields into the VORBIS_COMMENT block, adding one if needed
+    pub fn set_replay_gain(&mut self, track: ReplayGain, album: Option<ReplayGain>) -> Result<()> {
+        let mut comment = self
+            .find::<VorbisComment>()
+            .transpose()?
+            .unwrap_or_else(|| {
+                VorbisComment::new(concat!("rotic-flac ", env!("CARGO_PKG_VERSION")))
+            });
+        comment.set_replay_gain(track, album);
+        self.set(comment);
+        Ok(())
+    }
+}
+
+/// Measures the files, tags each with its track gain and, when `album` is set, with the gain
+/// of all of them together. Returns the track gains and the album gain.
+///
+/// Every file is read and tagged before the first one is written, so a file that can't be
+/// tagged leaves all of them untouched. Only a failure while saving, such as an I/O error,
+/// leaves the files before it written
+pub fn tag_files<P: AsRef<Path>>(
+    paths: &[P],
+    album: bool,
+) -> Result<(Vec<ReplayGain>, Option<ReplayGain>)> {
+    if paths.is_empty() {
+        return Err(Custom("no files to tag".to_owned()));
+    }
+    let tracks = paths
+        .iter()
+        .map(Loudness::measure_path)
+        .collect::<Result<Vec<_>>>()?;
+    let album_gain = album.then(|| Loudness::album(&tracks).replay_gain());
+    let gains: Vec<_> = tracks.iter().map(Loudness::replay_gain).collect();
+    let mut editors = Vec::with_capacity(paths.len());
+    for (path, gain) in paths.iter().zip(&gains) {
+        let mut editor = MetadataEditor::open(path)?;
+        editor.set_replay_gain(*gain, album_gain)?;
+        editors.push(editor);
+    }
+    for (path, editor) in paths.iter().zip(&editors) {
+        editor.save_to_path(path)?;
+    }
+    Ok((gains, album_gain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::Block;
+    use crate::{encode, EncoderConfig};
+
+    /// Stereo 1 kHz sine with the given peak in dBFS
+    fn sine(sample_rate: u32, seconds: f64, dbfs: f64) -> Vec<i32> {
+        let amplitude = 10f64.powf(dbfs / 20.0) * 32767.0;
+        let len = (sample_rate as f64 * seconds) as usize;
+        (0..len)
+            .flat_map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let sample = (amplitude * (2.0 * std::f64::consts::PI * 1000.0 * t).sin()) as i32;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn measure(samples: &[i32]) -> Loudness {
+        let mut meter = LoudnessMeter::new(48000, 2, 16);
+        samples.chunks(4096 * 2).for_each(|chunk| meter.push(chunk));
+        meter.finish()
+    }
+
+    #[test]
+    fn sine_loudness() {
+        // a stereo 1 kHz sine is as loud in LUFS as its peak in dBFS
+        let loudness = measure(&sine(48000, 5.0, -20.0));
+        assert!((loudness.integrated().unwrap() + 20.0).abs() < 0.05);
+        let gain = loudness.replay_gain();
+        assert!((gain.gain - 2.0).abs() < 0.05);
+        assert!((gain.peak - 0.1).abs() < 0.001);
+
+        let quiet = measure(&sine(48000, 5.0, -30.0));
+        let album = Loudness::album([&loudness, &quiet]);
+        let together = measure(&[sine(48000, 5.0, -20.0), sine(48000, 5.0, -30.0)].concat());
+        let album_loudness = album.integrated().unwrap();
+        assert!((album_loudness - together.integrated().unwrap()).abs() < 0.05);
+        assert!(album_loudness < -20.0 && album_loudness > -30.0);
+        assert_eq!(album.peak(), loudness.peak());
+
+        assert_eq!(measure(&[0; 48000]).integrated(), None);
+        assert_eq!(measure(&sine(48000, 0.3, -20.0)).integrated(), None);
+        assert!(std::panic::catch_unwind(|| LoudnessMeter::new(48000, 0, 16)).is_err());
+    }
+
+    #[test]
+    fn tag_album() {
+        let dir = std::env::temp_dir();
+        let paths: Vec<_> = [-20.0, -28.0]
+            .iter()
+            .enumerate()
+            .map(|(i, dbfs)| {
+                let path = dir.join(format!("rotic-replay-gain-{}-{i}.flac", std::process::id()));
+                let samples = sine(48000, 2.0, *dbfs);
+                std::fs::write(
+                    &path,
+                    encode(&samples, EncoderConfig::new(48000, 2, 16)).unwrap(),
+                )
+                .unwrap();
+                path
+            })
+            .collect();
+
+        // a broken VORBIS_COMMENT is only found after measuring
+        let broken = dir.join(format!(
+            "rotic-replay-gain-{}-broken.flac",
+            std::process::id()
+        ));
+        std::fs::copy(&paths[1], &broken).unwrap();
+        let mut editor = MetadataEditor::open(&broken).unwrap();
+        editor
+            .blocks_mut()
+            .insert(1, Block::new(false, 4, 3, vec![1, 2, 3]));
+        editor.save_to_path(&broken).unwrap();
+        let original = std::fs::read(&paths[0]).unwrap();
+        assert!(tag_files(&[&paths[0], &broken], true).is_err());
+        assert_eq!(std::fs::read(&paths[0]).unwrap(), original);
+        std::fs::remove_file(&broken).unwrap();
+
+        let (tracks, album) = tag_files(&paths, true).unwrap();
+        assert!((tracks[0].gain - 2.0).abs() < 0.05);
+        assert!((tracks[1].gain - 10.0).abs() < 0.05);
+        let album = album.unwrap();
+        for (path, track) in paths.iter().zip(&tracks) {
+            let comment: VorbisComment =
+                MetadataEditor::open(path).unwrap().find().unwrap().unwrap();
+            let tagged = comment.track_replay_gain().unwrap();
+            assert!((tagged.gain - track.gain).abs() < 0.01);
+            assert!((tagged.peak - track.peak).abs() < 1e-6);
+            assert!((comment.album_replay_gain().unwrap().gain - album.gain).abs() < 0.01);
+            assert_eq!(comment.get(REFERENCE), Some("-18.00 LUFS"));
+            assert!(Loudness::measure_path(path).is_ok());
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::decoder::{checked_block, FrameBuffer, Next, LINEAR_SEEK_LEN};
+use crate::frame::AudioBlock;
+use crate::metadata::{SeekTable, StreamInfo};
+use crate::Result;
+
+/// What the reader has to do for a [`SampleSeek`] to go on
+pub(crate) enum SeekStep {
+    /// Move the reader to this stream offset, the frame buffer already starts there
+    Seek(u64),
+    /// More data has to be read with [`FrameBuffer::spare`]
+    Fill,
+    /// The block holding the sample, `None` if it is past the end
+    Done(Option<AudioBlock>),
+}
+
+enum Probe {
+    /// Checks that the frame at a seek point starts with its sample
+    Point(u64),
+    /// Looks for a frame in the middle of the searched range
+    Middle,
+}
+
+enum State {
+    /// Finds the first valid frame starting in `start..limit`
+    Probe {
+        probe: Probe,
+        start: u64,
+        limit: u64,
+    },
+    /// Decodes frames from `low` until the one holding the sample
+    Linear,
+}
+
+/// Seeking without the I/O, shared by the sync and async decoders: uses the SEEKTABLE, then
+/// bisects between frame offsets known to start at or before the sample and after it
+pub(crate) struct SampleSeek {
+    sample: u64,
+    low: u64,
+    high: u64,
+    /// Offset of the first seek point after the sample
+    later: Option<u64>,
+    state: State,
+    /// Offset the reader has to move to before the frame buffer is used
+    pending: Option<u64>,
+}
+
+impl SampleSeek {
+    /// Seeks in the frames in `audio_offset..end`
+    pub(crate) fn new(
+        sample: u64,
+        audio_offset: u64,
+        end: u64,
+        seek_table: Option<&SeekTable>,
+    ) -> SampleSeek {
+        let mut seek = Self {
+            sample,
+            low: audio_offset,
+            high: end,
+            later: None,
+            state: State::Linear,
+            pending: None,
+        };
+        if let Some(seek_table) = seek_table {
+            seek.later = seek_table
+                .seek_points()
+                .filter(|p| p.sample_number > sample)
+                .min_by_key(|p| p.sample_number)
+                .map(|p| audio_offset.saturating_add(p.stream_offset));
+            if let Some(point) = seek_table.nearest(sample) {
+                let offset = audio_offset.saturating_add(point.stream_offset);
+                if offset < end {
+                    seek.probe(Probe::Point(point.sample_number), offset, end);
+                    return seek;
+                }
+            }
+        }
+        seek.bound_by_later();
+        seek.narrow();
+        seek
+    }
+
+    pub(crate) fn next(
+        &mut self,
+        frames: &mut FrameBuffer,
+        stream_info: &StreamInfo,
+    ) -> Result<SeekStep> {
+        loop {
+            if let Some(offset) = self.pending.take() {
+                frames.reset(offset);
+                return Ok(SeekStep::Seek(offset));
+            }
+            match &self.state {
+                State::Probe { limit, .. } if frames.position() >= *limit => self.probed(None),
+                State::Probe { limit, .. } => match frames.next_valid(stream_info)? {
+                    Next::Frame(offset, frame) if offset < *limit => {
+                        self.probed(Some((offset, frame.block.first_sample())))
+                    }
+                    Next::Fill => return Ok(SeekStep::Fill),
+                    _ => self.probed(None),
+                },
+                State::Linear => match frames.next(stream_info)? {
+                    Next::Frame(offset, frame) => {
+                        let mut block = checked_block(Some((offset, frame)))?.unwrap();
+                        let first_sample = block.first_sample();
+                        if first_sample + block.block_size() as u64 > self.sample {
+                            block.skip_samples(self.sample.saturating_sub(first_sample) as usize);
+                            return Ok(SeekStep::Done(Some(block)));
+                        }
+                    }
+                    Next::Fill => return Ok(SeekStep::Fill),
+                    Next::End => return Ok(SeekStep::Done(None)),
+                },
+            }
+        }
+    }
+
+    fn probe(&mut self, probe: Probe, start: u64, limit: u64) {
+        self.state = State::Probe {
+            probe,
+            start,
+            limit,
+        };
+        self.pending = Some(start);
+    }
+
+    /// Updates the range with the offset and first sample of the probed frame
+    fn probed(&mut self, found: Option<(u64, u64)>) {
+        let State::Probe { probe, start, .. } = &self.state else {
+            return;
+        };
+        match (probe, found) {
+            (Probe::Point(sample), Some((offset, first_sample)))
+                if offset == *start && first_sample == *sample =>
+            {
+                self.low = offset
+            }
+            (Probe::Point(_), _) => {}
+            (Probe::Middle, Some((offset, first_sample))) if first_sample <= self.sample => {
+                self.low = offset
+            }
+            (Probe::Middle, _) => self.high = *start,
+        }
+        if let Probe::Point(_) = probe {
+            self.bound_by_later();
+        }
+        self.narrow();
+    }
+
+    fn bound_by_later(&mut self) {
+        // a malformed table can put a later sample in front of the verified point
+        if let Some(later) = self.later.filter(|&later| later > self.low) {
+            self.high = self.high.min(later);
+        }
+    }
+
+    /// Probes the middle of the range, or decodes from its start once it is short
+    fn narrow(&mut self) {
+        if self.high - self.low > LINEAR_SEEK_LEN {
+            let middle = self.low + (self.high - self.low) / 2;
+            self.probe(Probe::Middle, middle, self.high);
+        } else {
+            self.state = State::Linear;
+            self.pending = Some(self.low);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use md5::{Digest, Md5};
+
+use crate::decoder::{FrameBuffer, Next};
+use crate::frame::DecodedFrame;
+use crate::metadata::StreamInfo;
+use crate::Result;
+
+/// A frame whose CRC-16 doesn't match its content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrcError {
+    /// Byte offset of the frame from the start of the stream
+    pub offset: u64,
+    pub frame_number: u64,
+    pub first_sample: u64,
+}
+
+/// A frame which couldn't be decoded at all, e.g. because of a damaged header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameError {
+    /// Byte offset of the frame from the start of the stream
+    pub offset: u64,
+    /// Number of frames decoded before it
+    pub frame_number: u64,
+    pub message: String,
+}
+
+/// Result of decoding a whole stream with [`Decoder::verify`](crate::Decoder::verify)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// MD5 stored in STREAMINFO, all zero when the encoder didn't compute one
+    pub expected_md5: [u8; 16],
+    /// MD5 of the decoded samples
+    pub computed_md5: [u8; 16],
+    /// Total samples per channel stored in STREAMINFO, 0 if unknown
+    pub expected_samples: u64,
+    /// Samples per channel decoded
+    pub decoded_samples: u64,
+    pub crc_errors: Vec<CrcError>,
+    /// Frames which failed to decode, reading resumed at the next frame found after each
+    pub frame_errors: Vec<FrameError>,
+}
+
+impl Verification {
+    pub fn has_md5(&self) -> bool {
+        self.expected_md5 != [0; 16]
+    }
+    pub fn md5_matches(&self) -> bool {
+        self.expected_md5 == self.computed_md5
+    }
+    /// No damaged frames, and the MD5 and sample count match when STREAMINFO has them
+    pub fn is_valid(&self) -> bool {
+        self.crc_errors.is_empty()
+            && self.frame_errors.is_empty()
+            && (!self.has_md5() || self.md5_matches())
+            && (self.expected_samples == 0 || self.expected_samples == self.decoded_samples)
+    }
+}
+
+/// Hashes samples the way STREAMINFO's MD5 is computed: little-endian,
+/// interleaved, each sample in the fewest whole bytes holding `bps` bits
+pub(crate) fn update_md5(md5: &mut Md5, samples: &[i32], bps: u8) {
+    let bytes = (bps as usize).div_ceil(8);
+    samples
+        .iter()
+        .for_each(|s| md5.update(&s.to_le_bytes()[..bytes]));
+}
+
+/// Collects the state of a running verification
+pub(crate) struct Verifier {
+    md5: Md5,
+    expected_md5: [u8; 16],
+    expected_samples: u64,
+    decoded_samples: u64,
+    frame_number: u64,
+    crc_errors: Vec<CrcError>,
+    frame_errors: Vec<FrameError>,
+    /// Looking for a valid frame after one which failed to decode
+    resync: bool,
+}
+
+impl Verifier {
+    pub(crate) fn new(stream_info: &StreamInfo) -> Verifier {
+        Self {
+            md5: Md5::new(),
+            expected_md5: stream_info.md5,
+            expected_samples: stream_info.total_samples,
+            decoded_samples: 0,
+            frame_number: 0,
+            crc_errors: Vec::new(),
+            frame_errors: Vec::new(),
+            resync: false,
+        }
+    }
+    /// Reads and checks the next frame. A frame which fails to decode is recorded and
+    /// reading resumes at the next frame whose header decodes
+    pub(crate) fn next(
+        &mut self,
+        frames: &mut FrameBuffer,
+        stream_info: &StreamInfo,
+    ) -> Result<Next> {
+        let next = match self.resync {
+            true => frames.resync(stream_info, false)?,
+            false => match frames.next(stream_info) {
+                Ok(next) => next,
+                Err(e) => {
+                    self.frame_errors.push(FrameError {
+                        offset: frames.position(),
+                        frame_number: self.frame_number,
+                        message: e.to_string(),
+                    });
+                    self.resync = true;
+                    frames.resync(stream_info, false)?
+                }
+            },
+        };
+        if let Next::Frame(offset, frame) = &next {
+            self.resync = false;
+            self.push(*offset, frame);
+        }
+        Ok(next)
+    }
+    fn push(&mut self, offset: u64, frame: &DecodedFrame) {
+        let block = &frame.block;
+        if !frame.crc_valid {
+            self.crc_errors.push(CrcError {
+                offset,
+                frame_number: self.frame_number,
+                first_sample: block.first_sample(),
+            });
+        }
+        update_md5(&mut self.md5, block.samples(), block.bits_per_sample());
+        self.decoded_samples += block.block_size() as u64;
+        self.frame_number += 1;
+    }
+    pub(crate) fn finish(self) -> Verification {
+        Verification {
+            expected_md5: self.expected_md5,
+            computed_md5: self.md5.finalize().into(),
+            expected_samples: self.expected_samples,
+            decoded_samples: self.decoded_samples,
+            crc_errors: self.crc_errors,
+            frame_errors: self.frame_errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::metadata_len;
+    use crate::frame::{decode_frame, FrameHeader};
+    use crate::{encode, read_from_bytes, Decoder, EncoderConfig};
+
+    fn stream() -> Vec<u8> {
+        let samples: Vec<i32> = (0..10_000).map(|i| (i * 37) % 1001 - 500).collect();
+        encode(&samples, EncoderConfig::new(8000, 2, 12)).unwrap()
+    }
+
+    #[test]
+    fn damaged_frames() {
+        let samples: Vec<i32> = (0..40_000).map(|i| (i * 37) % 1001 - 500).collect();
+        let mut bytes = encode(&samples, EncoderConfig::new(8000, 2, 12)).unwrap();
+        let stream_info = Decoder::new(&bytes[..]).unwrap().stream_info().clone();
+        let mut offsets = vec![metadata_len(&read_from_bytes(&bytes).unwrap())];
+        while let Ok(frame) =
+            decode_frame(&bytes[*offsets.last().unwrap() as usize..], &stream_info)
+        {
+            offsets.push(offsets.last().unwrap() + frame.len as u64);
+        }
+        offsets.pop();
+        assert_eq!(offsets.len(), 5);
+        // CRC-8 of the header of frame 1, CRC-16 of frame 2 right behind it and of frame 4
+        bytes[offsets[1] as usize + 5] ^= 1;
+        bytes[offsets[3] as usize - 1] ^= 1;
+        *bytes.last_mut().unwrap() ^= 1;
+
+        let verification = Decoder::new(&bytes[..]).unwrap().verify().unwrap();
+        assert!(!verification.is_valid());
+        let [error] = &verification.frame_errors[..] else {
+            panic!("expected one damaged frame");
+        };
+        assert_eq!((error.offset, error.frame_number), (offsets[1], 1));
+        let crc_errors: Vec<_> = verification.crc_errors.iter().map(|e| e.offset).collect();
+        assert_eq!(crc_errors, [offsets[2], offsets[4]]);
+        assert_eq!(verification.decoded_samples, 20_000 - 4096);
+    }
+
+    #[test]
+    fn verify_md5_and_crc() {
+        let bytes = stream();
+        let verification = Decoder::new(&bytes[..]).unwrap().verify().unwrap();
+        assert!(verification.has_md5());
+        assert!(verification.is_valid());
+        assert_eq!(verification.decoded_samples, 5000);
+
+        // the last two bytes are the CRC-16 of the last frame
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 0x10;
+        let verification = Decoder::new(&corrupt[..]).unwrap().verify().unwrap();
+        assert!(verification.md5_matches());
+        assert!(!verification.is_valid());
+        let [error] = verification.crc_errors[..] else {
+            panic!("expected one CRC error");
+        };
+        assert_eq!((error.frame_number, error.first_sample), (1, 4096));
+        let header = FrameHeader::parse(&corrupt[error.offset as usize..]).unwrap();
+        assert_eq!(header.number(), 1);
+
+        // STREAMINFO's MD5 takes the last 16 bytes of the block
+        let mut corrupt = bytes;
+        corrupt[8 + 33] ^= 1;
+        let verification = Decoder::new(&corrupt[..]).unwrap().verify().unwrap();
+        assert!(verification.crc_errors.is_empty());
+        assert!(!verification.md5_matches());
+        assert!(!verification.is_valid());
+    }
+}
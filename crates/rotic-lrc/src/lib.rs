@@ -0,0 +1,419 @@
+//! LRC lyrics: time tagged lines, ID tags such as `[ar:...]` and `[offset:...]`, and the
+//! enhanced format with `<mm:ss.xx>` word timings. Text without any time tag is kept as
+//! unsynced lines so plain lyrics fields can go through the same model.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A word of an enhanced LRC line and the time it starts at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub time: Duration,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LyricLine {
+    /// `None` for unsynced lyrics
+    pub time: Option<Duration>,
+    pub text: String,
+    /// Word timings, empty unless the line uses the enhanced format
+    pub words: Vec<Word>,
+}
+
+impl LyricLine {
+    pub fn new(time: Duration, text: impl Into<String>) -> LyricLine {
+        Self {
+            time: Some(time),
+            text: text.into(),
+            words: Vec::new(),
+        }
+    }
+    /// Line made of timed words, its text is the words put together
+    pub fn with_words(time: Duration, words: Vec<Word>) -> LyricLine {
+        Self {
+            time: Some(time),
+            text: words.iter().map(|w| w.text.as_str()).collect(),
+            words,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Lrc {
+    /// ID tags other than `offset`, in file order
+    pub tags: Vec<(String, String)>,
+    /// `[offset:...]` in milliseconds, positive values show the lyrics earlier
+    pub offset: i64,
+    /// Synced lines sorted by time, or unsynced lines in text order. A line without a time tag
+    /// in synced lyrics stays after the line it follows in the file
+    pub lines: Vec<LyricLine>,
+}
+
+/// Keys of the ID tags in the LRC format, `#` is a comment
+const ID_TAGS: [&str; 10] = [
+    "ti", "ar", "al", "au", "by", "re", "ve", "length", "offset", "#",
+];
+
+/// Parses `mm:ss`, `mm:ss.x`, `mm:ss.xx`, `mm:ss.xxx` or `mm:ss:xx`
+fn parse_time(s: &str) -> Option<Duration> {
+    let (minutes, rest) = s.trim().split_once(':')?;
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (rest, ""),
+    };
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(minutes) || !digits(seconds) || (!fraction.is_empty() && !digits(fraction)) {
+        return None;
+    }
+    let seconds: u64 = seconds.parse().ok()?;
+    if seconds >= 60 || fraction.len() > 3 {
+        return None;
+    }
+    let millis = match fraction.len() {
+        0 => 0,
+        len => fraction.parse::<u64>().ok()? * 10u64.pow(3 - len as u32),
+    };
+    let minutes: u64 = minutes.parse().ok()?;
+    let seconds = minutes.checked_mul(60)?.checked_add(seconds)?;
+    Some(Duration::from_millis(
+        seconds.checked_mul(1000)?.checked_add(millis)?,
+    ))
+}
+
+/// `mm:ss.xx`, with milliseconds when centiseconds would lose precision
+struct Time(Duration);
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.0.as_millis();
+        let (minutes, seconds) = (millis / 60_000, millis / 1000 % 60);
+        match millis % 10 {
+            0 => write!(f, "{minutes:02}:{seconds:02}.{:02}", millis % 1000 / 10),
+            _ => write!(f, "{minutes:02}:{seconds:02}.{:03}", millis % 1000),
+        }
+    }
+}
+
+/// Splits the text of a line at its `<mm:ss.xx>` word timings, text before the first one
+/// starts at the time of the line
+fn parse_words(time: Duration, text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut current = Word {
+        time,
+        text: String::new(),
+    };
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let marker = rest[start + 1..]
+            .find('>')
+            .and_then(|end| Some((end, parse_time(&rest[start + 1..start + 1 + end])?)));
+        let Some((end, word_time)) = marker else {
+            current.text.push_str(&rest[..=start]);
+            rest = &rest[start + 1..];
+            continue;
+        };
+        current.text.push_str(&rest[..start]);
+        if !current.text.is_empty() {
+            words.push(current);
+        }
+        current = Word {
+            time: word_time,
+            text: String::new(),
+        };
+        rest = &rest[start + end + 2..];
+    }
+    current.text.push_str(rest);
+    if words.is_empty() && current.time == time {
+        // no word timings at all
+        return Vec::new();
+    }
+    if !current.text.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+impl Lrc {
+    pub fn parse(text: &str) -> Lrc {
+        let mut lrc = Lrc::default();
+        // lines with the time they are sorted by, untimed ones take it from the line before
+        let mut lines = Vec::new();
+        let mut last = Duration::ZERO;
+        for line in text.trim_start_matches('\u{feff}').lines() {
+            let line = line.trim();
+            let mut rest = line;
+            let mut times = Vec::new();
+            while let Some((tag, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                match parse_time(tag) {
+                    Some(time) => times.push(time),
+                    None => break,
+                }
+                rest = after;
+            }
+            if !times.is_empty() {
+                let rest = rest.trim_start();
+                for time in times {
+                    let words = parse_words(time, rest);
+                    let line = match words.is_empty() {
+                        true => LyricLine::new(time, rest),
+                        false => LyricLine::with_words(time, words),
+                    };
+                    lines.push((time, line));
+                    last = time;
+                }
+                continue;
+            }
+            let id_tag = line
+                .strip_prefix('[')
+                .and_then(|l| l.strip_suffix(']'))
+                .and_then(|l| l.split_once(':'))
+                .filter(|(key, _)| ID_TAGS.iter().any(|tag| tag.eq_ignore_ascii_case(key)));
+            match id_tag {
+                Some((key, value)) if key.eq_ignore_ascii_case("offset") => {
+                    lrc.offset = value.trim().parse().unwrap_or(0);
+                }
+                Some((key, value)) => lrc.tags.push((key.to_owned(), value.trim().to_owned())),
+                None => lines.push((
+                    last,
+                    LyricLine {
+                        text: line.to_owned(),
+                        ..Default::default()
+                    },
+                )),
+            }
+        }
+        match lines.iter().any(|(_, l)| l.time.is_some()) {
+            true => {
+                // blank lines only separate the verses of synced lyrics
+                lines.retain(|(_, l)| l.time.is_some() || !l.text.is_empty());
+                lines.sort_by_key(|(time, _)| *time);
+                lrc.lines = lines.into_iter().map(|(_, l)| l).collect();
+            }
+            false => {
+                // plain lyrics, blank lines at the edges don't matter
+                let start = lines.iter().position(|(_, l)| !l.text.is_empty());
+                let end = lines.iter().rposition(|(_, l)| !l.text.is_empty());
+                if let (Some(start), Some(end)) = (start, end) {
+                    lrc.lines = lines.drain(start..=end).map(|(_, l)| l).collect();
+                }
+            }
+        }
+        lrc
+    }
+    /// Reads a sidecar `.lrc` file
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Lrc> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+    pub fn save_to_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+    /// Path of the sidecar `.lrc` file of an audio file
+    pub fn sidecar_path(audio: impl AsRef<Path>) -> PathBuf {
+        audio.as_ref().with_extension("lrc")
+    }
+
+    /// Whether the lines have time tags
+    pub fn is_synced(&self) -> bool {
+        self.lines.iter().any(|l| l.time.is_some())
+    }
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+    /// Replaces the value of the tag, or appends it
+    pub fn set_tag(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let (key, value) = (key.into(), value.into());
+        match self
+            .tags
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(&key))
+        {
+            Some(tag) => tag.1 = value,
+            None => self.tags.push((key, value)),
+        }
+    }
+    pub fn title(&self) -> Option<&str> {
+        self.tag("ti")
+    }
+    pub fn artist(&self) -> Option<&str> {
+        self.tag("ar")
+    }
+    pub fn album(&self) -> Option<&str> {
+        self.tag("al")
+    }
+    /// Time a synced line is shown at once the offset is applied
+    pub fn display_time(&self, line: &LyricLine) -> Option<Duration> {
+        let millis = i64::try_from(line.time?.as_millis()).unwrap_or(i64::MAX);
+        let millis = millis.saturating_sub(self.offset);
+        Some(Duration::from_millis(millis.max(0) as u64))
+    }
+    /// Line to show at `position` of the playback, `None` before the first one
+    pub fn line_at(&self, position: Duration) -> Option<&LyricLine> {
+        self.lines
+            .iter()
+            .filter(|l| l.time.is_some())
+            .take_while(|l| self.display_time(l).is_some_and(|time| time <= position))
+            .last()
+    }
+    /// The lyrics without time tags
+    pub fn plain_text(&self) -> String {
+        let lines: Vec<_> = self.lines.iter().map(|l| l.text.as_str()).collect();
+        lines.join("\n")
+    }
+}
+
+impl fmt::Display for Lrc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.tags {
+            writeln!(f, "[{key}:{value}]")?;
+        }
+        if self.offset != 0 {
+            writeln!(f, "[offset:{:+}]", self.offset)?;
+        }
+        for line in &self.lines {
+            if let Some(time) = line.time {
+                write!(f, "[{}]", Time(time))?;
+            }
+            match line.words.is_empty() {
+                true => f.write_str(&line.text)?,
+                false => {
+                    for word in &line.words {
+                        write!(f, "<{}>{}", Time(word.time), word.text)?;
+                    }
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn parse_and_write() {
+        let text = "\u{feff}[ti:Song]\n[ar:Someone]\n[offset:+500]\n\n\
+            [00:12.00][01:02.50]Chorus\n[00:05.5]Intro\n[00:20.123]<00:20.123>Word <00:21.00>by <00:21.50>word\n\
+            [00:30:25]Late <not a time>";
+        let lrc = Lrc::parse(text);
+        assert_eq!((lrc.title(), lrc.artist()), (Some("Song"), Some("Someone")));
+        assert_eq!(lrc.offset, 500);
+        let times: Vec<_> = lrc.lines.iter().map(|l| l.time.unwrap()).collect();
+        assert_eq!(
+            times,
+            [ms(5500), ms(12000), ms(20123), ms(30250), ms(62500)]
+        );
+        assert_eq!(lrc.lines[1].text, "Chorus");
+        assert_eq!(lrc.lines[3].text, "Late <not a time>");
+
+        let words = &lrc.lines[2];
+        assert_eq!(words.text, "Word by word");
+        assert_eq!(
+            words.words,
+            [
+                Word {
+                    time: ms(20123),
+                    text: "Word ".into()
+                },
+                Word {
+                    time: ms(21000),
+                    text: "by ".into()
+                },
+                Word {
+                    time: ms(21500),
+                    text: "word".into()
+                },
+            ]
+        );
+
+        let written = lrc.to_string();
+        assert!(written.starts_with("[ti:Song]\n[ar:Someone]\n[offset:+500]\n[00:05.50]Intro\n"));
+        assert!(written.contains("[00:20.123]<00:20.123>Word <00:21.00>by <00:21.50>word\n"));
+        assert_eq!(Lrc::parse(&written), lrc);
+
+        // shown 500 ms early
+        assert_eq!(lrc.line_at(ms(5000)).unwrap().text, "Intro");
+        assert_eq!(lrc.line_at(ms(11600)).unwrap().text, "Chorus");
+        assert!(lrc.line_at(ms(4999)).is_none());
+    }
+
+    #[test]
+    fn untimed_lines_in_synced_lyrics() {
+        let text = "[ti:Song]\n[#:a comment]\n[Chorus: Someone]\n[00:01.00]One\n\n\
+            (guitar solo)\n[00:10.00]Two\n[00:02.00]Three\n";
+        let lrc = Lrc::parse(text);
+        assert_eq!(
+            lrc.tags,
+            [
+                ("ti".into(), "Song".into()),
+                ("#".into(), "a comment".into())
+            ]
+        );
+        let lines: Vec<_> = lrc
+            .lines
+            .iter()
+            .map(|l| (l.time, l.text.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (None, "[Chorus: Someone]"),
+                (Some(ms(1000)), "One"),
+                (None, "(guitar solo)"),
+                (Some(ms(2000)), "Three"),
+                (Some(ms(10000)), "Two"),
+            ]
+        );
+        assert!(lrc.is_synced());
+        assert_eq!(lrc.line_at(ms(1500)).unwrap().text, "One");
+        assert_eq!(lrc.line_at(ms(2000)).unwrap().text, "Three");
+        assert!(lrc.plain_text().contains("(guitar solo)"));
+        assert_eq!(Lrc::parse(&lrc.to_string()), lrc);
+    }
+
+    #[test]
+    fn overflowing_times() {
+        let lrc = Lrc::parse("[307445734561826:00]Text\n");
+        assert!(!lrc.is_synced());
+        assert_eq!(lrc.lines[0].text, "[307445734561826:00]Text");
+
+        let mut lrc = Lrc::parse("[offset:-9223372036854775808]\n[00:01.00]One\n");
+        assert_eq!(lrc.offset, i64::MIN);
+        assert_eq!(lrc.display_time(&lrc.lines[0]), Some(ms(i64::MAX as u64)));
+        lrc.offset = 1;
+        let line = LyricLine::new(Duration::MAX, "Late");
+        assert_eq!(lrc.display_time(&line), Some(ms(i64::MAX as u64 - 1)));
+    }
+
+    #[test]
+    fn unsynced_lyrics() {
+        let lrc = Lrc::parse("\nFirst line\n\nSecond [verse]\n");
+        assert!(!lrc.is_synced());
+        assert_eq!(lrc.lines.len(), 3);
+        assert_eq!(lrc.plain_text(), "First line\n\nSecond [verse]");
+        assert_eq!(Lrc::parse(&lrc.to_string()), lrc);
+        assert!(lrc.line_at(ms(0)).is_none());
+
+        let path = std::env::temp_dir().join(format!("rotic-lrc-{}.flac", std::process::id()));
+        let sidecar = Lrc::sidecar_path(&path);
+        assert_eq!(sidecar.extension().unwrap(), "lrc");
+        let mut synced = Lrc::default();
+        synced.set_tag("ti", "Song");
+        synced.lines.push(LyricLine::new(ms(1000), "Hello"));
+        synced.save_to_path(&sidecar).unwrap();
+        assert_eq!(Lrc::from_path(&sidecar).unwrap(), synced);
+        std::fs::remove_file(sidecar).unwrap();
+    }
+}